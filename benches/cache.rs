@@ -0,0 +1,81 @@
+//! Compares the [CacheBackend]s of the [IdempotencyLayer] serving requests
+//! with an `Idempotency-Key`.
+//!
+//! `cargo bench --bench cache`
+use lib::middleware::cache::CacheBackend;
+use lib::middleware::cache::IdempotencyConfig;
+use lib::middleware::cache::IdempotencyLayer;
+
+use axum::routing::put;
+use axum::Router;
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::BenchmarkId;
+use criterion::Criterion;
+use hyper::http::HeaderValue;
+use hyper::Body;
+use hyper::Method;
+use hyper::Request;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use tokio::runtime::Runtime;
+use tower::ServiceExt;
+
+/// Requests sent at once by the concurrent benchmark.
+const CONCURRENCY: u64 = 64;
+
+fn router(rt: &Runtime, backend: CacheBackend) -> Router {
+    let config = IdempotencyConfig { methods: vec![Method::PUT], backend, ..Default::default() };
+    let (idempotency, mut cache_manager) = IdempotencyLayer::new(config);
+    rt.spawn(async move { cache_manager.run().await });
+    Router::new().route("/items", put(|| async { "item" })).route_layer(idempotency)
+}
+
+fn request(key: u64) -> Request<Body> {
+    let mut req = Request::builder().method(Method::PUT).uri("/items").body(Body::empty()).unwrap();
+    req.headers_mut().insert("Idempotency-Key", HeaderValue::from(key));
+    req
+}
+
+/// A new key, then its replay, one request at a time.
+fn sequential(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("sequential");
+    for backend in [CacheBackend::Actor, CacheBackend::Direct] {
+        let router = router(&rt, backend);
+        let keys = AtomicU64::new(0);
+        group.bench_function(BenchmarkId::from_parameter(format!("{backend:?}")), |b| {
+            b.to_async(&rt).iter(|| async {
+                let key = keys.fetch_add(1, Ordering::Relaxed);
+                router.clone().oneshot(request(key)).await.unwrap();
+                router.clone().oneshot(request(key)).await.unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+/// [CONCURRENCY] new keys at once, on a multi-threaded runtime.
+fn concurrent(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("concurrent");
+    for backend in [CacheBackend::Actor, CacheBackend::Direct] {
+        let router = router(&rt, backend);
+        let keys = AtomicU64::new(0);
+        group.bench_function(BenchmarkId::from_parameter(format!("{backend:?}")), |b| {
+            b.to_async(&rt).iter(|| async {
+                let first = keys.fetch_add(CONCURRENCY, Ordering::Relaxed);
+                let requests: Vec<_> = (first..first + CONCURRENCY)
+                    .map(|key| tokio::spawn(router.clone().oneshot(request(key))))
+                    .collect();
+                for request in requests {
+                    request.await.unwrap().unwrap();
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, sequential, concurrent);
+criterion_main!(benches);
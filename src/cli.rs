@@ -0,0 +1,118 @@
+//! The command-line interface of the server binary.
+use crate::config::Config;
+use crate::config::StorageBackend;
+use crate::obs::LogFormat;
+use crate::warehouse::Database;
+use crate::ServerResult;
+
+use clap::Args;
+use clap::Parser;
+use clap::Subcommand;
+use color_eyre::eyre::Context;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+#[command(name = "server", version, about = "Users API with idempotent requests")]
+pub struct Cli {
+    /// Serves the API when no command is given.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Serve the API until SIGINT or SIGTERM is received.
+    Serve(ServeArgs),
+    /// Validate the config and print it, without starting the server.
+    CheckConfig(ConfigArgs),
+    /// Apply the pending storage migrations.
+    Migrate(ConfigArgs),
+    /// Print the build information.
+    Version,
+}
+
+#[derive(Debug, Default, Args)]
+pub struct ConfigArgs {
+    /// The TOML config file; defaults to `$ICAPI_CONFIG`.
+    #[arg(short, long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Args)]
+pub struct ServeArgs {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+    /// The address and port to listen on, overriding the config.
+    #[arg(short, long, value_name = "ADDR")]
+    pub bind: Option<SocketAddr>,
+    /// The log format, overriding the config.
+    #[arg(long, value_enum)]
+    pub log_format: Option<LogFormat>,
+}
+
+impl Cli {
+    pub async fn run(self) -> ServerResult {
+        match self.command.unwrap_or(Command::Serve(ServeArgs::default())) {
+            Command::Serve(args) => crate::serve(args.load()?).await,
+            Command::CheckConfig(args) => {
+                let config = args.load()?;
+                let config = toml::to_string_pretty(&config).context("Config Serialization")?;
+                println!("{config}");
+                Ok(())
+            }
+            Command::Migrate(args) => migrate(&args.load()?),
+            Command::Version => {
+                println!("{}", version());
+                Ok(())
+            }
+        }
+    }
+}
+
+impl ConfigArgs {
+    /// Loads the config from the given file and the environment.
+    pub fn load(&self) -> ServerResult<Config> {
+        Ok(Config::load(self.config.as_deref())?)
+    }
+}
+
+impl ServeArgs {
+    /// Loads the config, overridden by the arguments.
+    pub fn load(&self) -> ServerResult<Config> {
+        let mut config = self.config.load()?;
+        if let Some(bind) = self.bind {
+            config.server.bind = bind;
+        }
+        if let Some(format) = self.log_format {
+            config.log.format = format;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+/// Applies the pending migrations of the configured storage.
+fn migrate(config: &Config) -> ServerResult {
+    let (StorageBackend::Sqlite, Some(path)) = (config.storage.backend, &config.storage.path)
+    else {
+        println!("Nothing to Migrate: {:?} Storage", config.storage.backend);
+        return Ok(());
+    };
+
+    let applied = Database::connect(path)?.migrate()?;
+    println!("{applied} Migrations Applied to {}", path.display());
+    Ok(())
+}
+
+/// The version, commit, target and profile the binary was built with.
+pub fn version() -> String {
+    format!(
+        "{} {} ({} {}, {})",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        env!("BUILD_GIT_SHA"),
+        env!("BUILD_TARGET"),
+        env!("BUILD_PROFILE"),
+    )
+}
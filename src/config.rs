@@ -0,0 +1,343 @@
+//! Server configuration, loaded from a TOML file and `ICAPI_*` environment
+//! variables.
+//!
+//! ```toml
+//! [server]
+//! bind = "0.0.0.0:8080"
+//!
+//! [cache]
+//! backend = "direct"
+//! ttl_secs = 3600
+//!
+//! [storage]
+//! backend = "sqlite"
+//! path = "icapi.db"
+//! email_reuse = "on_purge"
+//!
+//! [log]
+//! format = "json"
+//! dir = "/var/log/icapi"
+//! rotation = "hourly"
+//!
+//! [trace]
+//! endpoint = "http://localhost:4318/v1/traces"
+//! ```
+//!
+//! Any setting can be overridden by an environment variable named after its
+//! section and field, e.g. `ICAPI_SERVER_BIND` or `ICAPI_CACHE_TTL_SECS`.
+use crate::error::get_error_cause;
+use crate::middleware::cache::CacheBackend;
+use crate::middleware::cache::FailurePolicy;
+use crate::middleware::cache::IdempotencyConfig;
+use crate::obs::LogFormat;
+use crate::obs::LogRotation;
+use crate::server::DEFAULT_DRAIN;
+use crate::warehouse::CacheConfig;
+use crate::warehouse::EmailReuse;
+
+use hyper::Uri;
+use serde::Deserialize;
+use serde::Serialize;
+use std::env;
+use std::fmt::Debug;
+use std::fs;
+use std::net::Ipv4Addr;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use toml::Table;
+use toml::Value;
+use tracing_subscriber::filter::Targets;
+
+/// The prefix of the environment variables overriding the settings.
+pub const ENV_PREFIX: &str = "ICAPI_";
+/// The environment variable with the path of the config file, used when no
+/// path is given.
+pub const PATH_VAR: &str = "ICAPI_CONFIG";
+/// The environment variable with the log filter, used over `log.filter`.
+pub const LOG_VAR: &str = "RUST_LOG";
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerSettings,
+    pub cache: CacheSettings,
+    pub storage: StorageSettings,
+    pub log: LogSettings,
+    pub trace: TraceSettings,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSettings {
+    /// The address and port to listen on.
+    pub bind: SocketAddr,
+    /// The maximum size in bytes of a request body.
+    pub body_limit: usize,
+    /// How long in-flight requests may take to complete on shutdown.
+    pub drain_secs: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheSettings {
+    pub backend: CacheBackend,
+    /// How long a response is replayed for.
+    pub ttl_secs: u64,
+    /// The maximum number of cached responses; only the memory cache is
+    /// bounded, the sqlite one only by `ttl_secs`.
+    pub capacity: usize,
+    pub shards: usize,
+    pub mailbox: usize,
+    /// How long to wait for the cache on each access.
+    pub timeout_ms: u64,
+    pub on_failure: FailurePolicy,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageSettings {
+    pub backend: StorageBackend,
+    /// The database file, required by [StorageBackend::Sqlite].
+    pub path: Option<PathBuf>,
+    /// When the emails of deleted users can be taken again.
+    pub email_reuse: EmailReuse,
+}
+
+/// Where users and cached responses are stored.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// Lost on restart.
+    #[default]
+    Memory,
+    /// Persisted to [StorageSettings::path].
+    Sqlite,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogSettings {
+    pub format: LogFormat,
+    /// A [Targets] filter, used unless `RUST_LOG` is set.
+    pub filter: String,
+    /// Where to write rolling log files instead of stdout.
+    pub dir: Option<PathBuf>,
+    pub rotation: LogRotation,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TraceSettings {
+    /// The OTLP/HTTP endpoint of the collector spans are exported to; spans
+    /// are not exported when unset.
+    pub endpoint: Option<String>,
+    /// The `service.name` of the exported spans.
+    pub service_name: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum ConfigError {
+    #[error("Failed to Read Config {}", .path.display())]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Invalid Config: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Invalid Config: {0}")]
+    Invalid(String),
+}
+
+impl Config {
+    /// Loads the config from the file at `path`, or at [PATH_VAR] when no
+    /// `path` is given, overridden by the environment.
+    pub fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
+        let path = path.map(Path::to_path_buf).or_else(|| env::var_os(PATH_VAR).map(PathBuf::from));
+        let toml = match path {
+            Some(path) => {
+                fs::read_to_string(&path).map_err(|source| ConfigError::Read { path, source })?
+            }
+            None => String::new(),
+        };
+        Self::from_sources(&toml, env::vars())
+    }
+
+    /// Parses the config from `toml`, overridden by the `ICAPI_*` variables
+    /// in `env`, and validates it.
+    pub fn from_sources(
+        toml: &str,
+        env: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, ConfigError> {
+        let mut table: Table = toml::from_str(toml)?;
+        let defaults = Value::try_from(Config::default()).expect("Config Is Serializable");
+        for (var, raw) in env {
+            // Which takes precedence over the log.filter; see obs::get_sub_with.
+            if var == LOG_VAR && Targets::from_str(&raw).is_err() {
+                return Err(ConfigError::Invalid(format!(
+                    "{LOG_VAR} must be a valid tracing filter"
+                )));
+            }
+            let Some(setting) = var.strip_prefix(ENV_PREFIX) else { continue };
+            if var == PATH_VAR {
+                continue;
+            }
+            let Some((section, field)) = setting.split_once('_') else {
+                return Err(ConfigError::Invalid(format!("Unknown Setting {var}")));
+            };
+
+            let (section, field) = (section.to_lowercase(), field.to_lowercase());
+            let default = defaults.get(&section).and_then(|defaults| defaults.get(&field));
+            let value = env_value(default, &raw);
+
+            table
+                .entry(section.clone())
+                .or_insert_with(|| Value::Table(Table::new()))
+                .as_table_mut()
+                .ok_or_else(|| ConfigError::Invalid(format!("{section} Is Not a Section")))?
+                .insert(field, value);
+        }
+
+        let config: Config = Value::Table(table).try_into()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Rejects settings the server cannot start with.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |reason: &str| Err(ConfigError::Invalid(reason.to_string()));
+        let (server, cache) = (&self.server, &self.cache);
+
+        if server.bind.port() == 0 {
+            return invalid("server.bind must have a non-zero port");
+        }
+        if server.body_limit == 0 {
+            return invalid("server.body_limit must be positive");
+        }
+        if cache.ttl_secs == 0 {
+            return invalid("cache.ttl_secs must be positive");
+        }
+        // Not rejected with the sqlite storage, which has no capacity, since
+        // it cannot be told apart from the default.
+        if cache.capacity == 0 {
+            return invalid("cache.capacity must be positive");
+        }
+        if cache.shards == 0 {
+            return invalid("cache.shards must be positive");
+        }
+        if cache.mailbox == 0 {
+            return invalid("cache.mailbox must be positive");
+        }
+        if cache.timeout_ms == 0 {
+            return invalid("cache.timeout_ms must be positive");
+        }
+        if self.storage.backend == StorageBackend::Sqlite && self.storage.path.is_none() {
+            return invalid("storage.path is required by the sqlite backend");
+        }
+        if Targets::from_str(&self.log.filter).is_err() {
+            return invalid("log.filter must be a valid tracing filter");
+        }
+        if let Some(endpoint) = &self.trace.endpoint {
+            let uri = endpoint.parse::<Uri>();
+            if !uri.is_ok_and(|uri| uri.scheme().is_some() && uri.host().is_some()) {
+                return invalid("trace.endpoint must be an absolute URL");
+            }
+        }
+        Ok(())
+    }
+
+    /// The [IdempotencyConfig] of the `POST`, `PUT`, `PATCH` and `DELETE`
+    /// routes.
+    pub fn idempotency(&self) -> IdempotencyConfig {
+        let cache = &self.cache;
+        IdempotencyConfig {
+            backend: cache.backend,
+            shards: cache.shards,
+            mailbox: cache.mailbox,
+            timeout: Duration::from_millis(cache.timeout_ms),
+            on_failure: cache.on_failure,
+            body_limit: self.server.body_limit,
+            cache: CacheConfig {
+                ttl: Duration::from_secs(cache.ttl_secs),
+                capacity: cache.capacity,
+                ..CacheConfig::default()
+            },
+            ..IdempotencyConfig::default()
+        }
+    }
+
+    /// How long in-flight requests may take to complete on shutdown.
+    pub fn drain(&self) -> Duration {
+        Duration::from_secs(self.server.drain_secs)
+    }
+}
+
+/// Parses an environment variable as a TOML value of the type of the
+/// `default` of its setting, e.g. a number or a boolean; *otherwise* as a
+/// string, like the settings that are strings or unset by default, e.g.
+/// paths.
+fn env_value(default: Option<&Value>, raw: &str) -> Value {
+    let string = || Value::String(raw.to_string());
+    match default {
+        None | Some(Value::String(_)) => string(),
+        Some(_) => toml::from_str::<Table>(&format!("value = {raw}"))
+            .ok()
+            .and_then(|mut table| table.remove("value"))
+            .unwrap_or_else(string),
+    }
+}
+
+impl ServerSettings {
+    /// The port the server listens on by default.
+    pub const DEFAULT_PORT: u16 = 8080;
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        Self {
+            bind: SocketAddr::from((Ipv4Addr::LOCALHOST, Self::DEFAULT_PORT)),
+            body_limit: IdempotencyConfig::DEFAULT_BODY_LIMIT,
+            drain_secs: DEFAULT_DRAIN.as_secs(),
+        }
+    }
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        Self {
+            backend: CacheBackend::default(),
+            ttl_secs: CacheConfig::DEFAULT_TTL.as_secs(),
+            capacity: CacheConfig::DEFAULT_CAPACITY,
+            shards: IdempotencyConfig::DEFAULT_SHARDS,
+            mailbox: IdempotencyConfig::DEFAULT_MAILBOX,
+            timeout_ms: IdempotencyConfig::DEFAULT_TIMEOUT.as_millis() as u64,
+            on_failure: FailurePolicy::default(),
+        }
+    }
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::default(),
+            filter: "info".to_string(),
+            dir: None,
+            rotation: LogRotation::default(),
+        }
+    }
+}
+
+impl Default for TraceSettings {
+    fn default() -> Self {
+        Self { endpoint: None, service_name: env!("CARGO_PKG_NAME").to_string() }
+    }
+}
+
+impl Debug for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        get_error_cause(self, f)
+    }
+}
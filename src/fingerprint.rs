@@ -0,0 +1,48 @@
+use axum::http::Method;
+use serde_json::Value;
+use sha2::Digest;
+use sha2::Sha256;
+use std::fmt::Display;
+
+/// A fingerprint identifies the payload of a request made with an
+/// [IKey](crate::ikey::IKey), so that reusing a key for a *different* request
+/// can be detected.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Fingerprint(pub String);
+
+impl Fingerprint {
+    /// Hashes the `method`, `path` (with any query) and canonicalized `body`
+    /// of a request.
+    ///
+    /// JSON bodies are canonicalized by re-serializing them, which orders the
+    /// keys and drops insignificant whitespace; other bodies are hashed as is.
+    pub fn new(method: &Method, path: &str, body: &[u8]) -> Self {
+        let body = match serde_json::from_slice::<Value>(body) {
+            Ok(json) => serde_json::to_vec(&json).expect("Value Is Serializable"),
+            Err(_) => body.to_vec(),
+        };
+
+        let mut hasher = Sha256::new();
+        hasher.update(method.as_str());
+        hasher.update([0]);
+        hasher.update(path);
+        hasher.update([0]);
+        hasher.update(body);
+        let digest = hasher.finalize();
+
+        let hex = digest.iter().map(|byte| format!("{byte:02x}")).collect();
+        Self(hex)
+    }
+}
+
+impl AsRef<str> for Fingerprint {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for Fingerprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
@@ -13,16 +13,18 @@ impl IKey {
     /// The maximum length for the *value*.
     pub const MAX_LEN: u8 = 255;
 
-    /// Parse and validate [IKey] from request headers.
+    /// Parse and validate [IKey] from request headers; *otherwise* the
+    /// `400` to answer a missing or malformed key with.
     pub fn from_headers(headers: &HeaderMap) -> Result<IKey, (StatusCode, String)> {
-        let Some(value) = headers
-            .get(Self::HEADER)
-            .and_then(|value| value.to_str().ok()) else {
-                return Err((StatusCode::INTERNAL_SERVER_ERROR, "IKey: Heder received, value missing".to_string()))
-            };
+        let Some(value) = headers.get(Self::HEADER) else {
+            return Err((StatusCode::BAD_REQUEST, format!("{} Header Is Missing", Self::HEADER)));
+        };
+        let Ok(value) = value.to_str() else {
+            let error = format!("{} Header Must Be Visible ASCII", Self::HEADER);
+            return Err((StatusCode::BAD_REQUEST, error));
+        };
 
-        let ikey = IKey::try_from(value.to_string())
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        let ikey = IKey::try_from(value.to_string()).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
 
         Ok(ikey)
     }
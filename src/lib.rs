@@ -1,13 +1,23 @@
+use color_eyre::eyre::Context;
 use color_eyre::Report;
+use config::Config;
+use config::StorageBackend;
 use server::UserApi;
-use std::net::Ipv4Addr;
 use tokio::net::TcpListener;
+use warehouse::Database;
+use warehouse::SqliteCache;
+use warehouse::SqliteUserRepository;
 use warehouse::UserRepository;
 
+pub mod cli;
+pub mod config;
 mod error;
-mod ikey;
-mod middleware;
+pub mod fingerprint;
+pub mod ikey;
+pub mod metrics;
+pub mod middleware;
 pub mod obs;
+pub mod page;
 mod routes;
 pub mod server;
 mod service;
@@ -17,12 +27,51 @@ pub mod warehouse;
 pub type ServerResult<T = ()> = Result<T, Report>;
 
 pub async fn try_main() -> ServerResult {
-    let sub = obs::get_sub();
+    let config = Config::load(None)?;
+    serve(config).await
+}
+
+/// Serves the API as configured, until it is shut down.
+pub async fn serve(config: Config) -> ServerResult {
+    let provider = match &config.trace.endpoint {
+        Some(endpoint) => Some(obs::otlp(endpoint, &config.trace.service_name)?),
+        None => None,
+    };
+    let tracer = provider.as_ref().map(obs::tracer);
+    let log = &config.log;
+    let sub = obs::get_sub_with(log.format, &log.filter, log.dir.as_deref(), log.rotation, tracer)?;
     obs::init_with(sub);
-    let socket = (Ipv4Addr::new(127, 0, 0, 1), 0);
-    let listener = TcpListener::bind(socket).await.expect("Socket is Bound");
+    let listener = TcpListener::bind(config.server.bind).await.context("Socket Binding Failed")?;
     let addr = listener.local_addr();
     tracing::info!("Bound: {:#?}", addr);
-    let pool = UserRepository::new();
-    UserApi::new(listener, pool).run().await
+
+    let idempotency = config.idempotency();
+    let email_reuse = config.storage.email_reuse;
+    let mut api = match config.storage.backend {
+        StorageBackend::Memory => {
+            let users = UserRepository::new().with_email_reuse(email_reuse);
+            UserApi::with_idempotency(listener, users, idempotency)
+        }
+        StorageBackend::Sqlite => {
+            let Some(path) = &config.storage.path else {
+                color_eyre::eyre::bail!("Storage Path Is Required");
+            };
+            let db = Database::open(path)?;
+            let cache = SqliteCache::new(db.clone(), &idempotency.cache);
+            let users =
+                SqliteUserRepository::new(db).with_email_reuse(email_reuse).recording_in(&cache);
+            UserApi::with_stores(listener, users, idempotency, |i| cache.shard(i))
+        }
+    };
+    api.drain = config.drain();
+    let served = api.run().await;
+
+    // Exports the last batch of spans.
+    if let Some(provider) = provider {
+        let shutdown = tokio::task::spawn_blocking(move || provider.shutdown()).await?;
+        if let Err(error) = shutdown {
+            tracing::error!("Span Export Shutdown Failed: {:#?}", error);
+        }
+    }
+    served
 }
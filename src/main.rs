@@ -1,8 +1,11 @@
+use lib::cli::Cli;
 use lib::ServerResult;
 
-#[tokio::main(flavor = "current_thread")]
+use clap::Parser;
+
+// Requests, and the shards of the cache, are served in parallel.
+#[tokio::main]
 async fn main() -> ServerResult {
     color_eyre::install()?;
-    lib::try_main().await?;
-    Ok(())
+    Cli::parse().run().await
 }
@@ -0,0 +1,202 @@
+//! Prometheus metrics of the API and its idempotency cache.
+use crate::middleware::cache::handle::CacheHandle;
+use crate::middleware::cache::handle::ShardStats;
+use crate::warehouse::Lock;
+
+use hyper::StatusCode;
+use prometheus::HistogramOpts;
+use prometheus::HistogramVec;
+use prometheus::IntCounterVec;
+use prometheus::IntGaugeVec;
+use prometheus::Opts;
+use prometheus::Registry;
+use prometheus::TextEncoder;
+use std::time::Duration;
+
+/// Every metric of a [UserApi](crate::server::UserApi), in its own
+/// [Registry].
+#[derive(Clone, Debug)]
+pub struct Metrics {
+    registry: Registry,
+    http: HttpMetrics,
+    cache: CacheMetrics,
+}
+
+/// Requests served, per method, route and status.
+#[derive(Clone, Debug)]
+struct HttpMetrics {
+    requests: IntCounterVec,
+    latency: HistogramVec,
+}
+
+/// What the [IdempotencyLayer](crate::middleware::cache::IdempotencyLayer)
+/// found for requests with an `Idempotency-Key`.
+#[derive(Clone, Debug)]
+pub struct IdempotencyMetrics {
+    /// Per outcome: `hit`, `miss`, `in_flight`, `mismatch` or `unavailable`.
+    lookups: IntCounterVec,
+    /// Replayed responses, per status.
+    replays: IntCounterVec,
+}
+
+/// The state of each cache shard, as last sampled by its manager.
+#[derive(Clone, Debug)]
+struct CacheMetrics {
+    entries: IntGaugeVec,
+    evictions: IntCounterVec,
+    mailbox: IntGaugeVec,
+}
+
+/// The outcome of looking up an `Idempotency-Key`.
+#[derive(Clone, Copy, Debug)]
+pub enum Lookup {
+    Hit,
+    Miss,
+    InFlight,
+    Mismatch,
+    Unavailable,
+}
+
+impl Metrics {
+    /// Registers the HTTP and cache metrics along with the `idempotency`
+    /// metrics of the layer.
+    pub fn new(idempotency: &IdempotencyMetrics) -> Self {
+        let registry = Registry::new();
+        let http = HttpMetrics::new();
+        let cache = CacheMetrics::new();
+
+        let collectors = [
+            Box::new(http.requests.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(http.latency.clone()),
+            Box::new(idempotency.lookups.clone()),
+            Box::new(idempotency.replays.clone()),
+            Box::new(cache.entries.clone()),
+            Box::new(cache.evictions.clone()),
+            Box::new(cache.mailbox.clone()),
+        ];
+        for collector in collectors {
+            registry.register(collector).expect("Metrics Are Unique");
+        }
+        Self { registry, http, cache }
+    }
+
+    /// Reads the samples of the cache shards, and encodes every metric in the
+    /// Prometheus text format.
+    pub async fn render(&self, cache: &CacheHandle) -> String {
+        match cache.stats().await {
+            Ok(shards) => self.cache.observe(&shards),
+            Err(error) => tracing::warn!("Cache Stats Unavailable: {:#?}", error),
+        }
+
+        let families = self.registry.gather();
+        TextEncoder::new().encode_to_string(&families).expect("Metrics Are Encodable")
+    }
+
+    /// Records a request to `route`, answered with `status` after `elapsed`.
+    pub fn request(&self, method: &str, route: &str, status: StatusCode, elapsed: Duration) {
+        let labels = [method, route, status.as_str()];
+        self.http.requests.with_label_values(&labels).inc();
+        self.http.latency.with_label_values(&labels).observe(elapsed.as_secs_f64());
+    }
+}
+
+impl HttpMetrics {
+    fn new() -> Self {
+        let labels = ["method", "route", "status"];
+        let requests =
+            IntCounterVec::new(Opts::new("http_requests_total", "HTTP requests served"), &labels)
+                .expect("Metric Is Valid");
+        let latency = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP request latency"),
+            &labels,
+        )
+        .expect("Metric Is Valid");
+        Self { requests, latency }
+    }
+}
+
+impl IdempotencyMetrics {
+    pub fn new() -> Self {
+        let lookups = IntCounterVec::new(
+            Opts::new("idempotency_lookups_total", "Idempotency-Key lookups per outcome"),
+            &["outcome"],
+        )
+        .expect("Metric Is Valid");
+        let replays = IntCounterVec::new(
+            Opts::new("idempotency_replays_total", "Cached responses replayed per status"),
+            &["status"],
+        )
+        .expect("Metric Is Valid");
+        Self { lookups, replays }
+    }
+
+    pub fn lookup(&self, lookup: Lookup) {
+        self.lookups.with_label_values(&[lookup.as_str()]).inc();
+    }
+
+    pub fn replay(&self, status: StatusCode) {
+        self.replays.with_label_values(&[status.as_str()]).inc();
+    }
+}
+
+impl Default for IdempotencyMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CacheMetrics {
+    fn new() -> Self {
+        let entries = IntGaugeVec::new(
+            Opts::new("idempotency_cache_entries", "Entries held per cache shard"),
+            &["shard"],
+        )
+        .expect("Metric Is Valid");
+        let evictions = IntCounterVec::new(
+            Opts::new("idempotency_cache_evictions_total", "Entries evicted per cache shard"),
+            &["shard"],
+        )
+        .expect("Metric Is Valid");
+        let mailbox = IntGaugeVec::new(
+            Opts::new("idempotency_mailbox_depth", "Messages queued per cache manager"),
+            &["shard"],
+        )
+        .expect("Metric Is Valid");
+        Self { entries, evictions, mailbox }
+    }
+
+    fn observe(&self, shards: &[ShardStats]) {
+        for (shard, stats) in shards.iter().enumerate() {
+            let shard = shard.to_string();
+            self.entries.with_label_values(&[&shard]).set(stats.entries as i64);
+            self.mailbox.with_label_values(&[&shard]).set(stats.mailbox as i64);
+
+            // The store counts evictions since it was created.
+            let evictions = self.evictions.with_label_values(&[&shard]);
+            evictions.inc_by(stats.evictions.saturating_sub(evictions.get()));
+        }
+    }
+}
+
+impl From<&Lock> for Lookup {
+    fn from(lock: &Lock) -> Self {
+        match lock {
+            Lock::Completed(_) => Lookup::Hit,
+            Lock::Acquired => Lookup::Miss,
+            Lock::InFlight => Lookup::InFlight,
+            Lock::Mismatch => Lookup::Mismatch,
+        }
+    }
+}
+
+impl Lookup {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Lookup::Hit => "hit",
+            Lookup::Miss => "miss",
+            Lookup::InFlight => "in_flight",
+            Lookup::Mismatch => "mismatch",
+            Lookup::Unavailable => "unavailable",
+        }
+    }
+}
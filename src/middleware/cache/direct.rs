@@ -0,0 +1,106 @@
+//! A cache accessed in place by each request, instead of through a
+//! [CacheManager](super::manager::CacheManager).
+use super::guard::ReleaseGuard;
+use super::handle::shard;
+use super::handle::ShardStats;
+use crate::fingerprint::Fingerprint;
+use crate::ikey::IKey;
+use crate::warehouse::CacheError;
+use crate::warehouse::CachedResponse;
+use crate::warehouse::DynStore;
+use crate::warehouse::Lock;
+
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::sync::MutexGuard;
+use tokio::time::Instant;
+
+/// An [IdempotencyStore](crate::warehouse::IdempotencyStore) per shard of the
+/// keys, each behind its own lock, so that requests with different keys
+/// rarely contend.
+///
+/// Expired entries of a shard are swept by the first access after
+/// [CacheConfig::sweep_every](crate::warehouse::CacheConfig::sweep_every)
+/// has passed since its last sweep.
+#[derive(Clone, Debug)]
+pub struct DirectCache {
+    shards: Arc<[Mutex<Shard>]>,
+    sweep_every: Duration,
+}
+
+#[derive(Debug)]
+struct Shard {
+    store: DynStore,
+    swept_at: Instant,
+}
+
+impl DirectCache {
+    /// Initialize a new [DirectCache] with a shard per store in `stores`.
+    pub fn new(stores: Vec<DynStore>, sweep_every: Duration) -> Self {
+        assert!(!stores.is_empty(), "At Least One Shard");
+        let swept_at = Instant::now();
+        let shards = stores.into_iter().map(|store| Mutex::new(Shard { store, swept_at }));
+        Self { shards: shards.collect(), sweep_every }
+    }
+
+    pub async fn set(&self, key: &IKey, res: &CachedResponse) -> Result<(), CacheError> {
+        self.shard(key).await.store.set(key, res).await
+    }
+
+    /// Locks `key` in place; should the caller stop waiting once the store
+    /// has started on the lock, e.g. on a timeout, the lock it may have taken
+    /// is released, since it would never be otherwise.
+    pub async fn lock(&self, key: &IKey, fingerprint: &Fingerprint) -> Result<Lock, CacheError> {
+        let mut shard = self.shard(key).await;
+        let guard = self.release_on_drop(key);
+        let lock = shard.store.lock(key, fingerprint).await;
+        guard.disarm();
+        lock
+    }
+
+    pub async fn delete(&self, key: &IKey) -> Result<(), CacheError> {
+        self.shard(key).await.store.delete(key).await
+    }
+
+    /// Locks every shard in turn, to check that none is held indefinitely.
+    pub async fn ping(&self) {
+        for shard in self.shards.iter() {
+            drop(shard.lock().await);
+        }
+    }
+
+    /// Samples the store of every shard in turn.
+    pub async fn stats(&self) -> Result<Vec<ShardStats>, CacheError> {
+        let mut stats = Vec::with_capacity(self.shards.len());
+        for shard in self.shards.iter() {
+            let mut shard = shard.lock().await;
+            let entries = shard.store.size().await?;
+            let evictions = shard.store.evictions();
+            stats.push(ShardStats { entries, evictions, mailbox: 0 });
+        }
+        Ok(stats)
+    }
+
+    /// A [ReleaseGuard] deleting the `key` of a lock whose caller is gone.
+    fn release_on_drop(&self, key: &IKey) -> ReleaseGuard {
+        let (cache, key) = (self.clone(), key.clone());
+        ReleaseGuard::new(async move {
+            tracing::warn!("Requester Dropped Before Lock, Releasing {key}");
+            if let Err(error) = cache.delete(&key).await {
+                tracing::error!("Failed to Release {key}: {:#?}", error);
+            }
+        })
+    }
+
+    /// Locks the shard responsible for `key`, sweeping it when it is due.
+    async fn shard(&self, key: &IKey) -> MutexGuard<'_, Shard> {
+        let mut shard = self.shards[shard(key, self.shards.len())].lock().await;
+        if shard.swept_at.elapsed() >= self.sweep_every {
+            let expired = shard.store.expire().await;
+            tracing::info!("Expired Entries Swept: {expired:?}");
+            shard.swept_at = Instant::now();
+        }
+        shard
+    }
+}
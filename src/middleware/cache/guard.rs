@@ -0,0 +1,44 @@
+//! Releases a locked key when its requester is dropped half way, e.g. when
+//! the client disconnects before the response is recorded.
+use std::future::Future;
+use std::pin::Pin;
+use tokio::runtime::Handle;
+use tracing::instrument::WithSubscriber;
+use tracing::Instrument;
+use tracing::Span;
+
+type Release = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Spawns its `release` when dropped, unless it was disarmed first.
+///
+/// The `release` is lazy, so it runs only on the runtime the guard is dropped
+/// on, and only if the guard is dropped while armed.
+#[must_use = "The Key Is Released as Soon as the Guard Is Dropped"]
+pub struct ReleaseGuard {
+    release: Option<Release>,
+}
+
+impl ReleaseGuard {
+    pub fn new(release: impl Future<Output = ()> + Send + 'static) -> Self {
+        Self { release: Some(Box::pin(release)) }
+    }
+
+    /// The key was recorded or released already, so nothing is left to do.
+    pub fn disarm(mut self) {
+        self.release = None;
+    }
+}
+
+impl Drop for ReleaseGuard {
+    fn drop(&mut self) {
+        let Some(release) = self.release.take() else {
+            return;
+        };
+        match Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(release.instrument(Span::current()).with_current_subscriber());
+            }
+            Err(_) => tracing::error!("Runtime Shut Down, Key Left Locked"),
+        }
+    }
+}
@@ -1,55 +1,261 @@
+use super::direct::DirectCache;
+use super::manager::Heartbeat;
+use super::manager::Sample;
 use super::msg::Msg;
+use crate::error::get_error_cause;
+use crate::error::ErrorBody;
+use crate::fingerprint::Fingerprint;
 use crate::ikey::IKey;
 use crate::warehouse::CacheError;
 use crate::warehouse::CachedResponse;
+use crate::warehouse::Lock;
 
-use color_eyre::eyre::Context;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::Json;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Debug;
+use std::future::Future;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc::Sender;
 use tokio::sync::oneshot;
-use tokio::sync::oneshot::channel;
+use tracing::Span;
 
 /// A handler to *command* and *query* the
-/// [CacheManager](crate::middleware::cache::manager::CacheManager).
+/// [CacheManager](crate::middleware::cache::manager::CacheManager), or to
+/// access a [DirectCache] in place; see [CacheBackend](super::CacheBackend).
+///
+/// Each [IKey] is routed by its hash to one of the shards, so that every
+/// access to a key goes through the same manager, or store.
 #[derive(Debug, Clone)]
 pub struct CacheHandle {
-    sender: Sender<Msg>,
+    backend: Backend,
+    timeout: Duration,
+}
+
+#[derive(Debug, Clone)]
+enum Backend {
+    /// The mailbox, the [Heartbeat] and the [Sample] of the manager of each
+    /// shard.
+    Actor(Arc<[Sender<Msg>]>, Arc<[Heartbeat]>, Arc<[Sample]>),
+    Direct(DirectCache),
+}
+
+/// A sample of a cache shard.
+#[derive(Clone, Debug, Default)]
+pub struct ShardStats {
+    /// The entries held by the store of the shard, expired or not.
+    pub entries: usize,
+    /// The entries evicted by the store since it was created.
+    pub evictions: u64,
+    /// The messages queued for the manager of the shard; always `0` for a
+    /// [DirectCache].
+    pub mailbox: usize,
+}
+
+#[derive(thiserror::Error)]
+pub enum CacheHandleError {
+    /// The manager has stopped, or stopped before it responded.
+    #[error("Cache Manager Is Not Running")]
+    Closed,
+    /// The cache did not respond in time, e.g. when it is overloaded.
+    #[error("Cache Did Not Respond Within {0:?}")]
+    Timeout(Duration),
+    /// The manager has not gone through its loop lately, e.g. when it is stuck
+    /// on its store.
+    #[error("Cache Manager Did Not Run Within {0:?}")]
+    Stalled(Duration),
+    /// The cache responded with an error.
+    #[error(transparent)]
+    Cache(#[from] CacheError),
 }
 
 impl CacheHandle {
-    pub fn new(sender: Sender<Msg>) -> Self {
-        Self { sender }
+    /// Initialize a new [CacheHandle] to the managers of the given `shards`,
+    /// beating the given `heartbeats` and publishing the given `samples`,
+    /// waiting up to `timeout` for each response.
+    pub fn new(
+        shards: Vec<Sender<Msg>>,
+        heartbeats: Vec<Heartbeat>,
+        samples: Vec<Sample>,
+        timeout: Duration,
+    ) -> Self {
+        assert!(!shards.is_empty(), "At Least One Shard");
+        assert_eq!(shards.len(), heartbeats.len(), "A Heartbeat per Shard");
+        assert_eq!(shards.len(), samples.len(), "A Sample per Shard");
+        let backend = Backend::Actor(shards.into(), heartbeats.into(), samples.into());
+        Self { backend, timeout }
     }
 
-    /// Given [IKey] exists in the cache, returns a [CachedResponse];
-    /// *otherwise* returns `None`.
-    #[tracing::instrument(name = "Check Cache for Response")]
-    pub async fn get(&self, key: &IKey) -> Option<CachedResponse> {
-        let key = key.clone();
-        let (ret, res) = channel();
-        let msg = Msg::Get { key, ret };
+    /// Initialize a new [CacheHandle] accessing the `cache` in place,
+    /// waiting up to `timeout` for each access.
+    pub fn direct(cache: DirectCache, timeout: Duration) -> Self {
+        Self { backend: Backend::Direct(cache), timeout }
+    }
 
-        self.sender.send(msg).await.context("Receiver Was Dropped").expect("Graceful Shutdown");
-        tracing::info!("Get Sent");
+    /// Maps `key` to `val` in [Cache](crate::warehouse::Cache);
+    /// *otherwise* returns a [CacheHandleError].
+    #[tracing::instrument(skip(val), fields(status = %val.status, body_len = val.body.len()))]
+    pub async fn set(&self, key: &IKey, val: &CachedResponse) -> Result<(), CacheHandleError> {
+        match &self.backend {
+            Backend::Actor(shards, ..) => {
+                let key = key.clone();
+                let val = val.clone();
+                let (ret, res) = oneshot::channel();
+                let shard = &shards[shard(&key, shards.len())];
+                let msg = Msg::Set { key, val, ret, span: Span::current() };
+                Ok(self.request(shard, msg, res).await??)
+            }
+            Backend::Direct(cache) => Ok(self.within(cache.set(key, val)).await??),
+        }
+    }
 
-        let res = res.await.context("Failed to Receive Response").expect("Graceful Shutdown");
-        tracing::info!("Get Response Received");
-        res
+    /// Marks [IKey] as being processed, unless a request with the same key
+    /// was seen already; see [Lock].
+    #[tracing::instrument(name = "Lock Key in Cache")]
+    pub async fn lock(
+        &self,
+        key: &IKey,
+        fingerprint: &Fingerprint,
+    ) -> Result<Lock, CacheHandleError> {
+        match &self.backend {
+            Backend::Actor(shards, ..) => {
+                let key = key.clone();
+                let fingerprint = fingerprint.clone();
+                let (ret, res) = oneshot::channel();
+                let shard = &shards[shard(&key, shards.len())];
+                let msg = Msg::Lock { key, fingerprint, ret, span: Span::current() };
+                Ok(self.request(shard, msg, res).await??)
+            }
+            Backend::Direct(cache) => Ok(self.within(cache.lock(key, fingerprint)).await??),
+        }
     }
 
-    /// Maps `key` to `val` in [Cache](crate::warehouse::Cache);
-    /// *otherwise* returns a [CacheError].
+    /// Removes [IKey] from the [Cache](crate::warehouse::Cache), releasing
+    /// the [Lock] on it.
     #[tracing::instrument]
-    pub async fn set(&self, key: &IKey, val: &CachedResponse) -> Result<(), CacheError> {
-        let key = key.clone();
-        let val = val.clone();
-        let (ret, res) = oneshot::channel();
-        let msg = Msg::Set { key, val, ret };
-
-        self.sender.send(msg).await.context("Receiver Was Dropped").expect("Graceful Shutdown");
-        tracing::info!("Set Sent");
-
-        let res = res.await.context("Failed to Receive Response").expect("Graceful Shutdown");
-        tracing::info!("Set Response Received");
-        res
+    pub async fn delete(&self, key: &IKey) -> Result<(), CacheHandleError> {
+        match &self.backend {
+            Backend::Actor(shards, ..) => {
+                let key = key.clone();
+                let (ret, res) = oneshot::channel();
+                let shard = &shards[shard(&key, shards.len())];
+                let msg = Msg::Delete { key, ret, span: Span::current() };
+                Ok(self.request(shard, msg, res).await??)
+            }
+            Backend::Direct(cache) => Ok(self.within(cache.delete(key)).await??),
+        }
+    }
+
+    /// Checks that every shard answers within the configured timeout, i.e.
+    /// that no manager has stopped or stalled.
+    #[tracing::instrument(name = "Ping Cache")]
+    pub async fn ping(&self) -> Result<(), CacheHandleError> {
+        match &self.backend {
+            Backend::Actor(shards, ..) => {
+                for shard in shards.iter() {
+                    let (ret, res) = oneshot::channel();
+                    self.request(shard, Msg::Ping { ret }, res).await?;
+                }
+                Ok(())
+            }
+            Backend::Direct(cache) => self.within(cache.ping()).await,
+        }
+    }
+
+    /// Checks that the manager of every shard is running and has gone through
+    /// its loop lately, without waiting in its mailbox behind the requests as
+    /// [CacheHandle::ping] does; a [DirectCache] is pinged.
+    #[tracing::instrument(name = "Check Cache Heartbeat")]
+    pub async fn alive(&self) -> Result<(), CacheHandleError> {
+        match &self.backend {
+            Backend::Actor(shards, heartbeats, _) => {
+                let within = Heartbeat::EVERY + self.timeout;
+                for (shard, heartbeat) in shards.iter().zip(heartbeats.iter()) {
+                    if shard.is_closed() {
+                        return Err(CacheHandleError::Closed);
+                    }
+                    if heartbeat.since().is_none_or(|since| since > within) {
+                        return Err(CacheHandleError::Stalled(within));
+                    }
+                }
+                Ok(())
+            }
+            Backend::Direct(cache) => self.within(cache.ping()).await,
+        }
+    }
+
+    /// Samples every shard for the [Metrics](crate::metrics::Metrics); the
+    /// managers are not waited for, but their last [Sample] is read.
+    #[tracing::instrument(name = "Sample Cache")]
+    pub async fn stats(&self) -> Result<Vec<ShardStats>, CacheHandleError> {
+        match &self.backend {
+            Backend::Actor(shards, _, samples) => {
+                let mut stats = Vec::with_capacity(shards.len());
+                for (shard, sample) in shards.iter().zip(samples.iter()) {
+                    let within = Heartbeat::EVERY + self.timeout;
+                    let sample = sample.last().ok_or(CacheHandleError::Stalled(within))?;
+                    let mailbox = shard.max_capacity() - shard.capacity();
+                    stats.push(ShardStats { mailbox, ..sample });
+                }
+                Ok(stats)
+            }
+            Backend::Direct(cache) => Ok(self.within(cache.stats()).await??),
+        }
+    }
+
+    /// Sends `msg` to the `shard` manager, and waits for its response on
+    /// `res`, for up to the configured timeout.
+    async fn request<T>(
+        &self,
+        shard: &Sender<Msg>,
+        msg: Msg,
+        res: oneshot::Receiver<T>,
+    ) -> Result<T, CacheHandleError> {
+        let kind = msg.to_string();
+        let request = async {
+            shard.send(msg).await.map_err(|_| CacheHandleError::Closed)?;
+            tracing::info!("{kind} Sent");
+            res.await.map_err(|_| CacheHandleError::Closed)
+        };
+
+        let res = self.within(request).await??;
+        tracing::info!("{kind} Response Received");
+        Ok(res)
+    }
+
+    /// Waits for `access` for up to the configured timeout.
+    async fn within<T>(&self, access: impl Future<Output = T>) -> Result<T, CacheHandleError> {
+        tokio::time::timeout(self.timeout, access)
+            .await
+            .map_err(|_| CacheHandleError::Timeout(self.timeout))
+    }
+}
+
+/// The shard out of `shards` responsible for `key`.
+pub(super) fn shard(key: &IKey, shards: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    (hasher.finish() % shards as u64) as usize
+}
+
+impl IntoResponse for CacheHandleError {
+    /// The cache is unavailable, whether its manager is not running or its
+    /// store failed, so the request may be retried later.
+    /// The cause is only logged, since it is of no use to the client.
+    fn into_response(self) -> Response {
+        tracing::error!("Cache Unavailable: {:#?}", self);
+        let error = "Idempotency Cache Is Unavailable, Retry Later".to_string();
+        (StatusCode::SERVICE_UNAVAILABLE, Json(ErrorBody { error })).into_response()
+    }
+}
+
+impl Debug for CacheHandleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        get_error_cause(self, f)
     }
 }
@@ -0,0 +1,238 @@
+//! A [Layer] providing idempotency for any route.
+use super::direct::DirectCache;
+use super::handle::CacheHandle;
+use super::manager::CacheManager;
+use super::manager::ShardedCacheManager;
+use super::process;
+use crate::metrics::IdempotencyMetrics;
+use crate::warehouse::Cache;
+use crate::warehouse::CacheConfig;
+use crate::warehouse::DynStore;
+use crate::warehouse::IdempotencyStore;
+
+use axum::body::Body;
+use axum::http::Method;
+use axum::http::Request;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use serde::Deserialize;
+use serde::Serialize;
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tower::Layer;
+use tower::Service;
+
+/// Configures which requests the [IdempotencyLayer] applies to, and the
+/// [IdempotencyStore] behind it.
+#[derive(Clone, Debug)]
+pub struct IdempotencyConfig {
+    /// Requests with any other method pass through the layer untouched.
+    pub methods: Vec<Method>,
+    /// Whether requests with one of the `methods` must have an
+    /// `Idempotency-Key`; *otherwise* requests without one pass through.
+    pub require_key: bool,
+    /// The `methods` whose requests must have an `Idempotency-Key` even when
+    /// it is not required, since a retry without one could overwrite a later
+    /// change with stale fields.
+    pub keyed_methods: Vec<Method>,
+    /// Retention and capacity of the cache.
+    pub cache: CacheConfig,
+    /// How requests access the cache.
+    pub backend: CacheBackend,
+    /// The number of [CacheManager]s, or [DirectCache] shards, the keys are
+    /// sharded across.
+    pub shards: usize,
+    /// The number of messages buffered for each [CacheManager].
+    pub mailbox: usize,
+    /// How long to wait for the [CacheManager] to answer each call.
+    pub timeout: Duration,
+    /// What to do with requests with an `Idempotency-Key` while the cache is
+    /// unavailable.
+    pub on_failure: FailurePolicy,
+    /// The maximum size in bytes of a request body buffered to compute its
+    /// [Fingerprint](crate::fingerprint::Fingerprint).
+    pub body_limit: usize,
+}
+
+/// How the [IdempotencyLayer] accesses the cache.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheBackend {
+    /// Send messages to the [CacheManager] responsible for each key.
+    #[default]
+    Actor,
+    /// Lock the [DirectCache] shard responsible for each key, and access it
+    /// in place; the [ShardedCacheManager] then has no shards to run.
+    Direct,
+}
+
+/// How the [IdempotencyLayer] handles requests when the [CacheManager] is
+/// unreachable, too slow or failing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailurePolicy {
+    /// Reject requests with an `Idempotency-Key` with `503`.
+    #[default]
+    Closed,
+    /// Process requests without idempotency guarantees, logging a warning.
+    Open,
+}
+
+/// A [Layer] that makes the wrapped service idempotent for requests with an
+/// `Idempotency-Key`.
+///
+/// The responses are cached by a [ShardedCacheManager], which must be
+/// [run](ShardedCacheManager::run) for the layer to make progress.
+#[derive(Clone, Debug)]
+pub struct IdempotencyLayer {
+    cache: CacheHandle,
+    config: Arc<IdempotencyConfig>,
+    metrics: IdempotencyMetrics,
+}
+
+/// The [Service] produced by [IdempotencyLayer].
+#[derive(Clone, Debug)]
+pub struct IdempotencyService<S> {
+    inner: S,
+    cache: CacheHandle,
+    config: Arc<IdempotencyConfig>,
+    metrics: IdempotencyMetrics,
+}
+
+impl IdempotencyLayer {
+    /// Initialize a new [IdempotencyLayer] with the [ShardedCacheManager]
+    /// serving it from an in-memory [Cache] per shard, which together hold
+    /// up to [CacheConfig::capacity] entries.
+    pub fn new(config: IdempotencyConfig) -> (Self, ShardedCacheManager) {
+        let shard = config.cache.shard(config.shards);
+        Self::with_stores(config, |_| Cache::with_config(&shard))
+    }
+
+    /// Initialize a new [IdempotencyLayer] with the [ShardedCacheManager]
+    /// serving shard `i` from the store `make(i)`.
+    ///
+    /// Each shard holds its own keys, so a store with a capacity of its own
+    /// is made with [CacheConfig::shard] for the shards to hold up to
+    /// [CacheConfig::capacity] together; a store the shards share is made
+    /// for its shard, e.g. with
+    /// [SqliteCache::shard](crate::warehouse::SqliteCache::shard), so that
+    /// only one of them sweeps it.
+    pub fn with_stores<S: IdempotencyStore>(
+        config: IdempotencyConfig,
+        mut make: impl FnMut(usize) -> S,
+    ) -> (Self, ShardedCacheManager<S>) {
+        let shards = config.shards.max(1);
+        let (cache, cache_manager) = match config.backend {
+            CacheBackend::Actor => {
+                let (senders, managers): (_, Vec<_>) = (0..shards)
+                    .map(|i| {
+                        let (sender, receiver) = mpsc::channel(config.mailbox);
+                        (sender, CacheManager::with_store(receiver, &config.cache, make(i)))
+                    })
+                    .unzip();
+                let heartbeats = managers.iter().map(CacheManager::heartbeat).collect();
+                let samples = managers.iter().map(CacheManager::sample).collect();
+                let cache = CacheHandle::new(senders, heartbeats, samples, config.timeout);
+                (cache, ShardedCacheManager::new(managers))
+            }
+            CacheBackend::Direct => {
+                let stores = (0..shards).map(|i| Box::new(make(i)) as DynStore).collect();
+                let direct = DirectCache::new(stores, config.cache.sweep_every);
+                (CacheHandle::direct(direct, config.timeout), ShardedCacheManager::new(vec![]))
+            }
+        };
+
+        let config = Arc::new(config);
+        let metrics = IdempotencyMetrics::new();
+        (Self { cache, config, metrics }, cache_manager)
+    }
+
+    pub fn config(&self) -> &IdempotencyConfig {
+        &self.config
+    }
+
+    /// The [CacheHandle] shared by the services of this layer.
+    pub fn cache(&self) -> &CacheHandle {
+        &self.cache
+    }
+
+    /// The [IdempotencyMetrics] recorded by the services of this layer.
+    pub fn metrics(&self) -> &IdempotencyMetrics {
+        &self.metrics
+    }
+}
+
+impl<S> Layer<S> for IdempotencyLayer {
+    type Service = IdempotencyService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        let cache = self.cache.clone();
+        let config = self.config.clone();
+        let metrics = self.metrics.clone();
+        IdempotencyService { inner, cache, config, metrics }
+    }
+}
+
+impl<S> Service<Request<Body>> for IdempotencyService<S>
+where
+    S: Service<Request<Body>, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        // The clone might not be ready; swap it with the one that is.
+        let clone = self.inner.clone();
+        let inner = std::mem::replace(&mut self.inner, clone);
+        let cache = self.cache.clone();
+        let config = self.config.clone();
+        let metrics = self.metrics.clone();
+        Box::pin(
+            async move { Ok(process(&cache, &config, &metrics, inner, req).await.into_response()) },
+        )
+    }
+}
+
+impl IdempotencyConfig {
+    /// The default number of [CacheManager]s.
+    pub const DEFAULT_SHARDS: usize = 4;
+    /// The default size of each [CacheManager] mailbox.
+    pub const DEFAULT_MAILBOX: usize = 8;
+    /// The default time to wait for each answer of the [CacheManager].
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+    /// The default maximum size of a request body, same as
+    /// [DefaultBodyLimit](axum::extract::DefaultBodyLimit).
+    pub const DEFAULT_BODY_LIMIT: usize = 2 * 1024 * 1024;
+}
+
+impl Default for IdempotencyConfig {
+    /// Applies to `POST`, `PUT`, `PATCH` and `DELETE` requests with an
+    /// `Idempotency-Key`, which `PUT` and `PATCH` requests must have,
+    /// failing closed.
+    fn default() -> Self {
+        Self {
+            methods: vec![Method::POST, Method::PUT, Method::PATCH, Method::DELETE],
+            require_key: false,
+            keyed_methods: vec![Method::PUT, Method::PATCH],
+            cache: CacheConfig::default(),
+            backend: CacheBackend::default(),
+            shards: Self::DEFAULT_SHARDS,
+            mailbox: Self::DEFAULT_MAILBOX,
+            timeout: Self::DEFAULT_TIMEOUT,
+            on_failure: FailurePolicy::default(),
+            body_limit: Self::DEFAULT_BODY_LIMIT,
+        }
+    }
+}
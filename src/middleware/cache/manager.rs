@@ -1,44 +1,288 @@
-//! A task manager that handles access to [Cache].
+//! A task manager that handles access to an [IdempotencyStore].
+use super::handle::ShardStats;
 use super::msg::Msg;
 use crate::warehouse::Cache;
+use crate::warehouse::CacheConfig;
+use crate::warehouse::IdempotencyStore;
 
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::PoisonError;
+use std::time::Duration;
 use tokio::sync::mpsc::Receiver;
+use tokio::sync::oneshot;
+use tokio::sync::watch;
+use tokio::time::interval;
+use tokio::time::Instant;
+use tokio::time::MissedTickBehavior;
+use tracing::instrument::WithSubscriber;
+use tracing::Instrument;
+use tracing::Span;
 
 /// Processes the received [Msg]s from the
-/// [CacheHandle](crate::middleware::cache::handle::CacheHandle).
+/// [CacheHandle](crate::middleware::cache::handle::CacheHandle), and
+/// periodically sweeps expired entries from the store, which is an in-memory
+/// [Cache] by default.
 #[derive(Debug)]
-pub struct CacheManager {
+pub struct CacheManager<S = Cache> {
     mailbox: Receiver<Msg>,
-    cache: Cache,
+    cache: S,
+    sweep_every: Duration,
+    heartbeat: Heartbeat,
+    sample: Sample,
+}
+
+/// When a [CacheManager] last went through its loop, at least every
+/// [Heartbeat::EVERY] while it runs; shared with the
+/// [CacheHandle](crate::middleware::cache::handle::CacheHandle) to tell
+/// whether it is alive without waiting in its mailbox.
+#[derive(Clone, Debug, Default)]
+pub struct Heartbeat(Arc<Mutex<Option<Instant>>>);
+
+/// The [ShardStats] a [CacheManager] last took of its store, every
+/// [Heartbeat::EVERY] and after each sweep; shared with the
+/// [CacheHandle](crate::middleware::cache::handle::CacheHandle), so that the
+/// [Metrics](crate::metrics::Metrics) do not wait in its mailbox.
+#[derive(Clone, Debug, Default)]
+pub struct Sample(Arc<Mutex<Option<ShardStats>>>);
+
+/// Runs a [CacheManager] per shard of the keys, each on its own task, so
+/// that requests with different keys are not serialized behind one mailbox;
+/// see [CacheHandle](crate::middleware::cache::handle::CacheHandle).
+#[derive(Debug)]
+pub struct ShardedCacheManager<S = Cache> {
+    shards: Vec<CacheManager<S>>,
 }
 
 impl CacheManager {
-    pub fn new(mailbox: Receiver<Msg>) -> Self {
-        let cache = Cache::new();
-        Self { mailbox, cache }
+    pub fn new(mailbox: Receiver<Msg>, config: &CacheConfig) -> Self {
+        Self::with_store(mailbox, config, Cache::with_config(config))
+    }
+}
+
+impl<S: IdempotencyStore> CacheManager<S> {
+    pub fn with_store(mailbox: Receiver<Msg>, config: &CacheConfig, store: S) -> Self {
+        let (heartbeat, sample) = Default::default();
+        Self { mailbox, cache: store, sweep_every: config.sweep_every, heartbeat, sample }
+    }
+
+    /// The [Heartbeat] of this manager.
+    pub fn heartbeat(&self) -> Heartbeat {
+        self.heartbeat.clone()
+    }
+
+    /// The [Sample] this manager takes of its store.
+    pub fn sample(&self) -> Sample {
+        self.sample.clone()
     }
 
     /// Processes messages from the channel until all senders are dropped
-    #[tracing::instrument(name = "Running Cache")]
     pub async fn run(&mut self) {
-        while let Some(mail) = self.mailbox.recv().await {
-            tracing::info!("Mail {mail} Received");
-            use Msg::*;
-            match mail {
-                Get { key, ret } => {
-                    tracing::info!("Processing GET");
-                    let cached_response = self.cache.get(&key).await.ok();
-                    tracing::warn!("GET executed");
-                    ret.send(cached_response).expect("Graceful Shutdown");
+        self.run_until(std::future::pending()).await
+    }
+
+    /// Processes messages from the channel until all senders are dropped, or
+    /// `shutdown` completes; see [CacheManager::stop].
+    #[tracing::instrument(name = "Running Cache", skip(shutdown))]
+    pub async fn run_until(&mut self, shutdown: impl Future<Output = ()>) {
+        let mut sweep = interval(self.sweep_every);
+        sweep.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut beat = interval(Heartbeat::EVERY);
+        beat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        tokio::pin!(shutdown);
+
+        loop {
+            self.heartbeat.beat();
+            tokio::select! {
+                mail = self.mailbox.recv() => {
+                    let Some(mail) = mail else { break };
+                    self.process(mail).await;
+                }
+
+                _ = sweep.tick() => {
+                    let expired = self.cache.expire().await;
+                    let evictions = self.cache.evictions();
+                    tracing::info!("Expired Entries Swept: {expired:?}, Evictions: {evictions}");
+                    self.take_sample().await;
                 }
 
-                Set { key, val, ret } => {
-                    tracing::info!("Processing SET");
-                    let res = self.cache.set(&key, &val).await;
-                    tracing::warn!("SET executed");
-                    ret.send(res).expect("Graceful Shutdown");
+                _ = beat.tick() => self.take_sample().await,
+
+                _ = &mut shutdown => {
+                    tracing::warn!("CacheManager Shutdown Requested");
+                    break;
+                }
+            }
+        }
+
+        self.stop().await;
+    }
+
+    /// Stops receiving messages, processes the ones already sent, and releases
+    /// the locks of the requests that were dropped before completing.
+    async fn stop(&mut self) {
+        self.mailbox.close();
+        while let Some(mail) = self.mailbox.recv().await {
+            self.process(mail).await;
+        }
+
+        match self.cache.release().await {
+            Ok(released) => {
+                tracing::warn!("CacheManager Stopped, {released} Interrupted Requests Released")
+            }
+            Err(error) => tracing::error!("CacheManager Stopped, Release Failed: {:#?}", error),
+        }
+    }
+
+    /// Publishes the size and evictions of the store to the [Sample].
+    async fn take_sample(&mut self) {
+        match self.cache.size().await {
+            Ok(entries) => {
+                let evictions = self.cache.evictions();
+                self.sample.publish(ShardStats { entries, evictions, mailbox: 0 });
+            }
+            Err(error) => tracing::error!("Failed to Sample Store: {:#?}", error),
+        }
+    }
+
+    /// Processes `mail` in the span of the request it was sent for, if any.
+    async fn process(&mut self, mail: Msg) {
+        let span = mail.span().cloned().unwrap_or_else(Span::current);
+        self.handle(mail).instrument(span).await
+    }
+
+    async fn handle(&mut self, mail: Msg) {
+        tracing::info!("Mail {mail} Received");
+        use Msg::*;
+        match mail {
+            Set { key, val, ret, .. } => {
+                tracing::info!("Processing SET");
+                let res = self.cache.set(&key, &val).await;
+                tracing::warn!("SET executed");
+                respond(ret, res);
+            }
+
+            Lock { key, fingerprint, ret, .. } => {
+                tracing::info!("Processing LOCK");
+                let res = self.cache.lock(&key, &fingerprint).await;
+                tracing::warn!("LOCK executed");
+                // A requester that stopped waiting, e.g. on a timeout, would
+                // never release the lock acquired for it.
+                if let Err(Ok(crate::warehouse::Lock::Acquired)) = ret.send(res) {
+                    tracing::warn!("Requester Dropped Before Lock, Releasing {key}");
+                    if let Err(error) = self.cache.delete(&key).await {
+                        tracing::error!("Failed to Release {key}: {:#?}", error);
+                    }
                 }
             }
+
+            Delete { key, ret, .. } => {
+                tracing::info!("Processing DELETE");
+                let res = self.cache.delete(&key).await;
+                tracing::warn!("DELETE executed");
+                respond(ret, res);
+            }
+
+            Ping { ret } => respond(ret, ()),
         }
     }
 }
+
+impl Heartbeat {
+    /// How often a running [CacheManager] beats, even when idle.
+    pub const EVERY: Duration = Duration::from_secs(1);
+
+    fn beat(&self) {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = Some(Instant::now());
+    }
+
+    /// How long ago the manager last beat; `None` when it never ran.
+    pub fn since(&self) -> Option<Duration> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner).map(|at| at.elapsed())
+    }
+}
+
+impl Sample {
+    fn publish(&self, stats: ShardStats) {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = Some(stats);
+    }
+
+    /// The last stats published; `None` when the manager never ran. The
+    /// mailbox depth is only known to the senders, so it is always `0`.
+    pub fn last(&self) -> Option<ShardStats> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+}
+
+/// Sends `res` back to the requester, which may have gone away already.
+fn respond<T>(ret: oneshot::Sender<T>, res: T) {
+    if ret.send(res).is_err() {
+        tracing::warn!("Requester Dropped Before Response");
+    }
+}
+
+impl<S: IdempotencyStore> ShardedCacheManager<S> {
+    pub fn new(shards: Vec<CacheManager<S>>) -> Self {
+        Self { shards }
+    }
+
+    /// The number of shards.
+    pub fn len(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.is_empty()
+    }
+
+    /// Runs every shard until all senders are dropped.
+    pub async fn run(&mut self) {
+        self.run_until(std::future::pending()).await
+    }
+
+    /// Runs every shard until all senders are dropped, or `shutdown`
+    /// completes, after which each shard is stopped; see
+    /// [CacheManager::run_until].
+    pub async fn run_until(&mut self, shutdown: impl Future<Output = ()>) {
+        let (stop, stopped) = watch::channel(false);
+        let tasks: Vec<_> = self
+            .shards
+            .drain(..)
+            .map(|mut shard| {
+                let mut stopped = stopped.clone();
+                // Shards log to the same subscriber as the caller.
+                let shard = async move {
+                    let stop = async move {
+                        let _ = stopped.wait_for(|stop| *stop).await;
+                    };
+                    shard.run_until(stop).await;
+                    shard
+                };
+                tokio::spawn(shard.with_current_subscriber())
+            })
+            .collect();
+        tracing::warn!("{} CacheManager Shards Spawned", tasks.len());
+
+        let joined = async {
+            let mut shards = Vec::with_capacity(tasks.len());
+            for task in tasks {
+                match task.await {
+                    Ok(shard) => shards.push(shard),
+                    Err(error) => tracing::error!("CacheManager Shard Failed: {:#?}", error),
+                }
+            }
+            shards
+        };
+        tokio::pin!(joined);
+        tokio::pin!(shutdown);
+
+        self.shards = tokio::select! {
+            shards = &mut joined => shards,
+            _ = &mut shutdown => {
+                let _ = stop.send(true);
+                joined.await
+            }
+        };
+    }
+}
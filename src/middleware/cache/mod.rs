@@ -1,92 +1,305 @@
+use self::guard::ReleaseGuard;
 use self::handle::CacheHandle;
+use self::handle::CacheHandleError;
+use crate::error::ErrorBody;
+use crate::fingerprint::Fingerprint;
 use crate::ikey::IKey;
-use crate::user::User;
+use crate::metrics::IdempotencyMetrics;
+use crate::metrics::Lookup;
 use crate::warehouse::CachedResponse;
+use crate::warehouse::Lock;
 
 use axum::body::boxed;
 use axum::body::Body;
 use axum::body::BoxBody;
+use axum::http::header;
+use axum::http::HeaderMap;
+use axum::http::HeaderValue;
 use axum::http::Request;
 use axum::http::StatusCode;
-use axum::middleware::Next;
 use axum::response::IntoResponse;
 use axum::response::Response;
-use axum::Extension;
 use axum::Json;
-use color_eyre::eyre::Context;
+use http_body::LengthLimitError;
+use http_body::Limited;
 use hyper::body;
-use serde_json::from_slice as DeserSlice;
+use serde::Serialize;
+use std::convert::Infallible;
+use tower::Service;
+use tower::ServiceExt;
 
+pub mod direct;
+pub mod guard;
 pub mod handle;
+pub mod layer;
 pub mod manager;
 pub mod msg;
 
+pub use layer::CacheBackend;
+pub use layer::FailurePolicy;
+pub use layer::IdempotencyConfig;
+pub use layer::IdempotencyLayer;
+pub use layer::IdempotencyService;
+
 type ErrorRes = Response<BoxBody>;
 
-/// Middleware for `POST /users`.
+/// Inserted into the request extensions once the [IdempotencyLayer] has
+/// locked the [IKey], so that a handler can record its response atomically
+/// with its side effects; see
+/// [UserStore::create_recorded](crate::warehouse::UserStore::create_recorded).
+#[derive(Clone, Debug)]
+pub struct IdempotencyContext {
+    pub key: IKey,
+    pub fingerprint: Fingerprint,
+}
+
+impl IdempotencyContext {
+    /// The [CachedResponse] the [IdempotencyLayer] caches for a
+    /// [Json](axum::Json) response with `status` and `body`.
+    pub fn json_response(&self, status: StatusCode, body: &impl Serialize) -> CachedResponse {
+        let body = serde_json::to_vec(body).expect("Body Is Serializable").into();
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        let fingerprint = self.fingerprint.clone();
+        CachedResponse { status, headers, body, fingerprint }
+    }
+
+    /// The [CachedResponse] the [IdempotencyLayer] caches for a response
+    /// with `status` and no body.
+    pub fn empty_response(&self, status: StatusCode) -> CachedResponse {
+        let fingerprint = self.fingerprint.clone();
+        CachedResponse { status, headers: HeaderMap::new(), body: Default::default(), fingerprint }
+    }
+}
+
+/// Middleware for any route wrapped in an [IdempotencyLayer].
 ///
-/// When `Idempotency-Key` header is provided, the `req` is further
-/// processed; *otherwise* the layer short-circuits.
-#[tracing::instrument(name = "Checking for Cached Response", skip(cache, req, next))]
-pub async fn process(
-    Extension(cache): Extension<CacheHandle>,
+/// When `Idempotency-Key` header is provided for one of the configured
+/// methods, the `req` is further processed, or rejected with `400` when the
+/// key is invalid; *otherwise* the layer short-circuits, unless the key is
+/// required.
+#[tracing::instrument(name = "Checking for Cached Response", skip_all)]
+pub async fn process<S>(
+    cache: &CacheHandle,
+    config: &IdempotencyConfig,
+    metrics: &IdempotencyMetrics,
+    inner: S,
     req: Request<Body>,
-    next: Next<Body>,
-) -> Result<Response, ErrorRes> {
-    let Ok(ref key) = IKey::from_headers(req.headers()) else {
+) -> Result<Response, ErrorRes>
+where
+    S: Service<Request<Body>, Response = Response, Error = Infallible>,
+{
+    if !config.methods.contains(req.method()) {
+        tracing::info!("Request with {} Not Idempotent", req.method());
+        return Ok(run(inner, req).await);
+    }
+
+    if !req.headers().contains_key(IKey::HEADER) {
+        if config.require_key || config.keyed_methods.contains(req.method()) {
+            tracing::warn!("Request without Required Key");
+            let error = format!("{} Header Is Required", IKey::HEADER);
+            return Err((StatusCode::BAD_REQUEST, Json(ErrorBody { error })).into_response());
+        }
         tracing::info!("Request without Key");
-        return Ok(next.run(req).await)
+        return Ok(run(inner, req).await);
+    }
+
+    // A malformed key must not run the request without idempotency.
+    let key = match IKey::from_headers(req.headers()) {
+        Ok(key) => key,
+        Err((status, error)) => {
+            tracing::warn!("Request with Invalid Key: {error}");
+            return Err((status, Json(ErrorBody { error })).into_response());
+        }
     };
 
     tracing::info!("Request with Key {:#?}", &key);
-    process_with_key(&cache, key, req, next).await
+    process_with_key(cache, config, metrics, &key, req, inner).await
 }
 
 /// Processes a `req` with an [IKey] in the header.
 ///
-/// When there is a cache hit for `key`, returns the cached response;
-/// *otherwise* processes the uncached request.
-async fn process_with_key(
+/// When there is a cache hit for `key`, replays the cached response. When a
+/// request with the same `key` is still in flight, returns `409`, and when
+/// the `key` was used for a different request, returns `422`; *otherwise*
+/// locks the `key` and processes the uncached request.
+///
+/// When the cache is unavailable, follows the configured [FailurePolicy].
+/// Either way, the outcome is recorded in the `metrics`.
+async fn process_with_key<S>(
     cache: &CacheHandle,
+    config: &IdempotencyConfig,
+    metrics: &IdempotencyMetrics,
     key: &IKey,
     req: Request<Body>,
-    next: Next<Body>,
-) -> Result<Response, ErrorRes> {
-    if let Some(cached) = cache.get(key).await {
-        tracing::warn!("Cache hit: ({key}, {cached})");
-        return Ok((StatusCode::CREATED, Json(cached.user)).into_response());
+    inner: S,
+) -> Result<Response, ErrorRes>
+where
+    S: Service<Request<Body>, Response = Response, Error = Infallible>,
+{
+    let (req, fingerprint) = fingerprint(req, config.body_limit).await?;
+    let lock = match cache.lock(key, &fingerprint).await {
+        Ok(lock) => lock,
+        Err(error) => {
+            metrics.lookup(Lookup::Unavailable);
+            tolerate(config, error).map_err(IntoResponse::into_response)?;
+            return Ok(run(inner, req).await);
+        }
     };
+    metrics.lookup(Lookup::from(&lock));
+    match lock {
+        Lock::Completed(cached) => {
+            tracing::warn!("Cache hit: ({key}, {cached})");
+            metrics.replay(cached.status);
+            let mut response = cached.into_response();
+            let replayed = HeaderValue::from_static("true");
+            response.headers_mut().insert(CachedResponse::REPLAYED_HEADER, replayed);
+            Ok(response)
+        }
+        Lock::InFlight => {
+            tracing::warn!("Request with {key} in progress");
+            let error = format!("Request with Idempotency-Key {key} is in progress");
+            Err((StatusCode::CONFLICT, Json(ErrorBody { error })).into_response())
+        }
+        Lock::Mismatch => {
+            tracing::warn!("Request with {key} does not match {fingerprint}");
+            let error = format!("Idempotency-Key {key} was used for a different request");
+            Err((StatusCode::UNPROCESSABLE_ENTITY, Json(ErrorBody { error })).into_response())
+        }
+        Lock::Acquired => {
+            tracing::warn!("Cache miss with {key}");
+            let mut req = req;
+            let context = IdempotencyContext { key: key.clone(), fingerprint: fingerprint.clone() };
+            req.extensions_mut().insert(context);
+            let guard = release_on_drop(cache, key);
+            process_uncached(cache, config, key, fingerprint, req, inner, guard).await
+        }
+    }
+}
 
-    tracing::warn!("Cache miss with {key}");
-    process_uncached(cache, key, req, next).await
+/// Buffers up to `limit` bytes of the body of `req` to compute its
+/// [Fingerprint], and returns the `req` rebuilt with the buffered body.
+async fn fingerprint(
+    req: Request<Body>,
+    limit: usize,
+) -> Result<(Request<Body>, Fingerprint), ErrorRes> {
+    let (parts, body) = req.into_parts();
+    let body = match body::to_bytes(Limited::new(body, limit)).await {
+        Ok(body) => body,
+        Err(error) if error.is::<LengthLimitError>() => {
+            let error = format!("Request body is larger than {limit} bytes");
+            return Err((StatusCode::PAYLOAD_TOO_LARGE, Json(ErrorBody { error })).into_response());
+        }
+        Err(_) => {
+            let error = "Failed to read the request body".to_string();
+            return Err((StatusCode::BAD_REQUEST, Json(ErrorBody { error })).into_response());
+        }
+    };
+
+    let path = parts.uri.path_and_query().map_or(parts.uri.path(), |path| path.as_str());
+    let fingerprint = Fingerprint::new(&parts.method, path, &body);
+    let req = Request::from_parts(parts, Body::from(body));
+    Ok((req, fingerprint))
 }
 
 /// Processes an uncached request with an `Idempotency-Key` header.
-async fn process_uncached(
+///
+/// Any response the handler returns is cached, except for server errors,
+/// which release the key so that the request can be retried. When the cache
+/// is unavailable, follows the configured [FailurePolicy].
+///
+/// Until the response is recorded or the key released, the `guard` releases
+/// the key should the request be dropped, e.g. on a client disconnect.
+async fn process_uncached<S>(
     cache: &CacheHandle,
+    config: &IdempotencyConfig,
     key: &IKey,
+    fingerprint: Fingerprint,
     req: Request<Body>,
-    layers: Next<Body>,
-) -> Result<Response, ErrorRes> {
+    inner: S,
+    guard: ReleaseGuard,
+) -> Result<Response, ErrorRes>
+where
+    S: Service<Request<Body>, Response = Response, Error = Infallible>,
+{
     // Run rest of the middleware layers, all the way down to the handler.
-    let response = layers.run(req).await;
+    let response = run(inner, req).await;
     // After the handler has run, only then upsert the cache
     let (head, body) = response.into_parts();
-    let body = body::to_bytes(body).await.context("Failed to convert body to bytes").unwrap();
-    match DeserSlice::<User>(&body) {
-        Ok(new_user) => {
-            tracing::info!("Uncached Request Proceessed");
-            let res = CachedResponse { status: head.status, user: new_user };
-            cache.set(key, &res).await.context("Cache Update Failed").expect("Cache Is Available");
-            tracing::warn!("Cache Miss Updated: {key} with {}", res.user);
-            Ok((res.status, Json(res.user)).into_response())
+    let body = body::to_bytes(body).await;
+    // The key is recorded or released below, and releasing it on a drop
+    // racing with the update would lose a recorded response.
+    guard.disarm();
+    let body = match body {
+        Ok(body) => body,
+        Err(error) => {
+            tracing::error!("Failed to Read Response Body: {:#?}", error);
+            if let Err(error) = cache.delete(key).await {
+                tolerate(config, error).map_err(IntoResponse::into_response)?;
+            }
+            let error = "Failed to read the response body".to_string();
+            return Err(
+                (StatusCode::INTERNAL_SERVER_ERROR, Json(ErrorBody { error })).into_response()
+            );
+        }
+    };
+
+    if head.status.is_server_error() {
+        tracing::warn!("Server Error {} Not Cached", head.status);
+        if let Err(error) = cache.delete(key).await {
+            tolerate(config, error).map_err(IntoResponse::into_response)?;
+        }
+        return Err(Response::from_parts(head, boxed(Body::from(body))));
+    }
+
+    tracing::info!("Uncached Request Proceessed");
+    let res = CachedResponse::new(&head, body.clone(), fingerprint);
+    match cache.set(key, &res).await {
+        Ok(()) => tracing::warn!("Cache Miss Updated: {key} with {res}"),
+        // The request was processed already, so its response is returned
+        // whatever the policy; the key is released, so that a retry is not
+        // rejected as in flight until it expires.
+        Err(error) => {
+            tracing::error!("Failed to Cache Response Under {key}: {:#?}", error);
+            if let Err(error) = cache.delete(key).await {
+                tracing::error!("Failed to Release {key}: {:#?}", error);
+            }
         }
-        Err(error_from_layer) => {
-            tracing::warn!("Deserialization Failure: {:#?}", error_from_layer);
-            // Layer did not return `User`, but something-else.
-            // Return that something-else without touching things.
-            // This can be cached too.
-            Err(Response::from_parts(head, boxed(Body::from(body))))
+    }
+    Ok(Response::from_parts(head, boxed(Body::from(body))))
+}
+
+/// A [ReleaseGuard] deleting the `key` locked in the `cache`.
+fn release_on_drop(cache: &CacheHandle, key: &IKey) -> ReleaseGuard {
+    let (cache, key) = (cache.clone(), key.clone());
+    ReleaseGuard::new(async move {
+        tracing::warn!("Request Dropped Before Its Response, Releasing {key}");
+        if let Err(error) = cache.delete(&key).await {
+            tracing::error!("Failed to Release {key}: {:#?}", error);
         }
+    })
+}
+
+/// Applies the [FailurePolicy] to an unavailable cache: failing open, the
+/// request proceeds without idempotency guarantees; *otherwise* it is
+/// rejected with `503`.
+fn tolerate(config: &IdempotencyConfig, error: CacheHandleError) -> Result<(), CacheHandleError> {
+    match config.on_failure {
+        FailurePolicy::Open => {
+            tracing::warn!("Cache Unavailable, Proceeding without Idempotency: {:#?}", error);
+            Ok(())
+        }
+        FailurePolicy::Closed => Err(error),
+    }
+}
+
+/// Runs the `inner` service, which never fails.
+async fn run<S>(inner: S, req: Request<Body>) -> Response
+where
+    S: Service<Request<Body>, Response = Response, Error = Infallible>,
+{
+    match inner.oneshot(req).await {
+        Ok(response) => response,
+        Err(never) => match never {},
     }
 }
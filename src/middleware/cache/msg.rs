@@ -1,29 +1,68 @@
+use crate::fingerprint::Fingerprint;
 use crate::ikey::IKey;
 use crate::warehouse::CacheError;
 use crate::warehouse::CachedResponse;
+use crate::warehouse::Lock;
 
 use std::fmt::Display;
 use tokio::sync::oneshot;
+use tracing::Span;
 
 /// - Responder is provided by the **client** of *manager*, iow. the *request*.
 /// - Responder is used by the **manager** to send the response back to the
-///  requester.
+///   requester.
 type Responder<T> = oneshot::Sender<T>;
-type GetResponder = Responder<Option<CachedResponse>>;
 type SetResponder = Responder<Result<(), CacheError>>;
+type LockResponder = Responder<Result<Lock, CacheError>>;
+type DeleteResponder = Responder<Result<(), CacheError>>;
+type PingResponder = Responder<()>;
 
 /// Defines the message types [CacheManager] and [CacheHandle] support.
 #[derive(Debug)]
 pub enum Msg {
-    Get { key: IKey, ret: GetResponder },
-    Set { key: IKey, val: CachedResponse, ret: SetResponder },
+    Set {
+        key: IKey,
+        val: CachedResponse,
+        ret: SetResponder,
+        span: Span,
+    },
+    Lock {
+        key: IKey,
+        fingerprint: Fingerprint,
+        ret: LockResponder,
+        span: Span,
+    },
+    Delete {
+        key: IKey,
+        ret: DeleteResponder,
+        span: Span,
+    },
+    /// Answered as soon as it is received, to check the manager is running.
+    Ping {
+        ret: PingResponder,
+    },
+}
+
+impl Msg {
+    /// The span of the request a keyed message is sent for, so that the
+    /// lines the manager logs while processing it correlate with the request.
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            Msg::Set { span, .. } | Msg::Lock { span, .. } | Msg::Delete { span, .. } => Some(span),
+            Msg::Ping { .. } => None,
+        }
+    }
 }
 
 impl Display for Msg {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Msg::Get { key, ret: _ } => write!(f, "GET with (k: {key})"),
-            Msg::Set { key, val, ret: _ } => write!(f, "SET with (k: {key}, v: {})", val.user.id),
+            Msg::Set { key, val, .. } => write!(f, "SET with (k: {key}, v: {})", val.status),
+            Msg::Lock { key, fingerprint, .. } => {
+                write!(f, "LOCK with (k: {key}, f: {fingerprint})")
+            }
+            Msg::Delete { key, .. } => write!(f, "DELETE with (k: {key})"),
+            Msg::Ping { ret: _ } => write!(f, "PING"),
         }
     }
 }
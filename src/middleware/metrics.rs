@@ -0,0 +1,27 @@
+//! Records the [Metrics] of every request.
+use crate::metrics::Metrics;
+
+use axum::extract::MatchedPath;
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::Extension;
+use std::sync::Arc;
+use tokio::time::Instant;
+
+/// Records the count and latency of requests to the matched route; see
+/// [Router::route_layer](axum::Router::route_layer).
+pub async fn track<B>(
+    Extension(metrics): Extension<Arc<Metrics>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let started = Instant::now();
+    let method = req.method().to_string();
+    let route = req.extensions().get::<MatchedPath>().map(|path| path.as_str().to_string());
+    let route = route.unwrap_or_else(|| "unmatched".to_string());
+
+    let response = next.run(req).await;
+    metrics.request(&method, &route, response.status(), started.elapsed());
+    response
+}
@@ -2,3 +2,5 @@
 //! for server.
 pub mod cache;
 pub mod ikey;
+pub mod metrics;
+pub mod span;
@@ -0,0 +1,14 @@
+//! Scopes each request to its [RequestSpan].
+use crate::obs::RequestSpan;
+
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use tracing::Span;
+
+/// Inserts the span of the request, made by
+/// [request_span](crate::obs::request_span), into its extensions.
+pub async fn scope<B>(mut req: Request<B>, next: Next<B>) -> Response {
+    req.extensions_mut().insert(RequestSpan(Span::current()));
+    next.run(req).await
+}
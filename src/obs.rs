@@ -1,11 +1,88 @@
+use crate::error::get_error_cause;
+use crate::ikey::IKey;
+
+use axum::http::HeaderMap;
+use axum::http::Request;
+use clap::ValueEnum;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::ExporterBuildError;
+use opentelemetry_otlp::Protocol;
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracer;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use serde::Deserialize;
+use serde::Serialize;
 use std::env;
+use std::fmt::Debug;
+use std::fmt::Display;
+use std::path::Path;
 use std::str::FromStr;
+use tokio::task::JoinError;
+use tracing::field::Empty;
 use tracing::subscriber::set_global_default;
+use tracing::Dispatch;
 use tracing::Level;
+use tracing::Span;
 use tracing::Subscriber;
+use tracing_appender::rolling::InitError;
+use tracing_appender::rolling::RollingFileAppender;
+use tracing_appender::rolling::Rotation;
 use tracing_error::ErrorLayer;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::ParseError;
 use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+
+/// The header identifying a request: accepted from the client, *otherwise*
+/// generated, and echoed in the response.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// The format of the logs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Multi-line, human readable logs.
+    #[default]
+    Pretty,
+    /// A line per event, prefixed with the fields of its spans.
+    Compact,
+    /// A JSON object per line, with the fields of its spans.
+    Json,
+}
+
+/// How often a new log file is started.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    #[default]
+    Daily,
+    /// Everything is appended to a single file.
+    Never,
+}
+
+/// Why a subscriber could not be built; see [get_sub_with].
+#[derive(thiserror::Error)]
+pub enum SubscriberError {
+    #[error("Log Filter Is Invalid")]
+    Filter(#[from] ParseError),
+    #[error("Log Directory Is Unusable")]
+    Dir(#[from] InitError),
+}
+
+/// The span of a request, inserted into its extensions so that handlers can
+/// record the fields only they know, e.g. the `user_id`.
+#[derive(Clone, Debug)]
+pub struct RequestSpan(pub Span);
 
 pub fn get_sub() -> impl Subscriber + Sync + Send {
     let filter = Targets::from_str(env::var("RUST_LOG").as_deref().unwrap_or("info"))
@@ -19,7 +96,154 @@ pub fn get_sub() -> impl Subscriber + Sync + Send {
         .with(filter)
 }
 
+/// A subscriber writing logs in the given `format`, filtered by `RUST_LOG`;
+/// *otherwise* by `filter`.
+///
+/// Logs are written to stdout, unless a `dir` is given, where they are
+/// written to `icapi.log` files started every `rotation`. Given a `tracer`,
+/// spans are exported too; see [otlp].
+pub fn get_sub_with(
+    format: LogFormat,
+    filter: &str,
+    dir: Option<&Path>,
+    rotation: LogRotation,
+    tracer: Option<SdkTracer>,
+) -> Result<Box<dyn Subscriber + Sync + Send>, SubscriberError> {
+    let filter = Targets::from_str(env::var("RUST_LOG").as_deref().unwrap_or(filter))?;
+    let writer = match dir {
+        Some(dir) => BoxMakeWriter::new(appender(dir, rotation)?),
+        None => BoxMakeWriter::new(std::io::stdout),
+    };
+    let fmt = tracing_subscriber::fmt()
+        .with_max_level(Level::TRACE)
+        .with_writer(writer)
+        .with_ansi(dir.is_none());
+
+    Ok(match format {
+        LogFormat::Pretty => Box::new(
+            fmt.pretty().finish().with(ErrorLayer::default()).with(exporter(tracer)).with(filter),
+        ),
+        LogFormat::Compact => Box::new(
+            fmt.compact().finish().with(ErrorLayer::default()).with(exporter(tracer)).with(filter),
+        ),
+        // Every span is listed, so the fields of the request span are on
+        // every line, whichever span the event is in.
+        LogFormat::Json => Box::new(
+            fmt.json()
+                .with_current_span(false)
+                .with_span_list(true)
+                .finish()
+                .with(ErrorLayer::default())
+                .with(exporter(tracer))
+                .with(filter),
+        ),
+    })
+}
+
+/// A provider of tracers exporting spans, in batches, to the OTLP/HTTP
+/// `endpoint` of a collector, e.g. `http://localhost:4318/v1/traces`, on
+/// behalf of `service`.
+///
+/// It must be [shut down](SdkTracerProvider::shutdown) for the last batch to
+/// be exported.
+pub fn otlp(endpoint: &str, service: &str) -> Result<SdkTracerProvider, ExporterBuildError> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_protocol(Protocol::HttpJson)
+        .with_endpoint(endpoint)
+        .build()?;
+    let resource = Resource::builder().with_service_name(service.to_string()).build();
+    Ok(SdkTracerProvider::builder().with_batch_exporter(exporter).with_resource(resource).build())
+}
+
+/// The tracer of the spans of this crate.
+pub fn tracer(provider: &SdkTracerProvider) -> SdkTracer {
+    provider.tracer(env!("CARGO_PKG_NAME"))
+}
+
+/// The layer exporting spans with the `tracer`, if any.
+fn exporter<S>(tracer: Option<SdkTracer>) -> Option<OpenTelemetryLayer<S, SdkTracer>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+/// The rolling `icapi.log` files in `dir`.
+fn appender(dir: &Path, rotation: LogRotation) -> Result<RollingFileAppender, InitError> {
+    let rotation = match rotation {
+        LogRotation::Minutely => Rotation::MINUTELY,
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Never => Rotation::NEVER,
+    };
+    RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix("icapi")
+        .filename_suffix("log")
+        .build(dir)
+}
+
+/// The span of `req`, with the request-scoped fields: the `request_id` and
+/// `idempotency_key` from its headers, and the `user_id` recorded by the
+/// handler; see [RequestSpan].
+pub fn request_span<B>(req: &Request<B>) -> Span {
+    let header = |name: &str| req.headers().get(name).and_then(|value| value.to_str().ok());
+    let span = tracing::info_span!(
+        "request",
+        method = %req.method(),
+        uri = %req.uri(),
+        request_id = header(REQUEST_ID_HEADER),
+        idempotency_key = header(IKey::HEADER),
+        user_id = Empty,
+    );
+
+    // Continues the trace of the caller from its `traceparent` header; fails
+    // only when spans are not exported, when there is no trace to continue.
+    let parent = TraceContextPropagator::new().extract(&Headers(req.headers()));
+    let _ = span.set_parent(parent);
+    span
+}
+
+/// The headers of a request, to extract its trace context from.
+struct Headers<'a>(&'a HeaderMap);
+
+impl Extractor for Headers<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+impl Debug for SubscriberError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        get_error_cause(self, f)
+    }
+}
+
+impl RequestSpan {
+    pub fn record_user_id(&self, id: impl Display) {
+        self.0.record("user_id", tracing::field::display(id));
+    }
+}
+
 /// This must only be called once.
 pub fn init_with(subscriber: impl Subscriber + Sync + Send) {
     set_global_default(subscriber).expect("Failed to Set Global Default Subscriber");
 }
+
+/// Runs the blocking `work` on the blocking pool of the runtime, within the
+/// current span and subscriber, so that its logs stay those of the request.
+pub async fn spawn_blocking<T: Send + 'static>(
+    work: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, JoinError> {
+    let span = Span::current();
+    let dispatch = tracing::dispatcher::get_default(Dispatch::clone);
+    tokio::task::spawn_blocking(move || {
+        tracing::dispatcher::with_default(&dispatch, || span.in_scope(work))
+    })
+    .await
+}
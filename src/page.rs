@@ -0,0 +1,170 @@
+//! Pages of [User]s: sorted, filtered by email, and resumed after a
+//! [Cursor].
+use crate::user::User;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// The query string of `GET /users`, as sent by the client.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ListParams {
+    /// The maximum number of users in the page; [UserQuery::DEFAULT_LIMIT]
+    /// when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+    pub sort: UserSort,
+    pub order: Order,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_prefix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_contains: Option<String>,
+    /// Where the previous page ended; see [Page::next].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+}
+
+/// What users are sorted by; ties are broken by their ids.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UserSort {
+    #[default]
+    Id,
+    Email,
+    CreatedAt,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// The validated [ListParams] a
+/// [UserStore](crate::warehouse::UserStore) lists users by.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UserQuery {
+    pub limit: usize,
+    pub sort: UserSort,
+    pub order: Order,
+    /// Only emails starting with it, case-sensitively.
+    pub email_prefix: Option<String>,
+    /// Only emails containing it, case-sensitively.
+    pub email_contains: Option<String>,
+    /// Only users after it, in the order of the query.
+    pub after: Option<Cursor>,
+}
+
+/// The position of a user when sorted by a [UserSort]: its sort key, then
+/// its id.
+///
+/// It is given to clients as URL-safe base64, opaque to them.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum Cursor {
+    Id(u64),
+    Email(String, u64),
+    /// Milliseconds since the Unix epoch.
+    CreatedAt(i64, u64),
+}
+
+/// A page of users, and where the next one starts.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Page {
+    pub users: Vec<User>,
+    /// The position of the last user, when more users follow it.
+    pub next: Option<Cursor>,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("Invalid Cursor")]
+pub struct InvalidCursor;
+
+impl UserQuery {
+    pub const DEFAULT_LIMIT: usize = 100;
+    pub const MAX_LIMIT: usize = 1000;
+
+    /// Whether the user with `email` at `position` belongs to the query,
+    /// regardless of the limit.
+    pub fn matches(&self, email: &str, position: &Cursor) -> bool {
+        let prefix = self.email_prefix.as_deref().is_none_or(|prefix| email.starts_with(prefix));
+        let contains = self.email_contains.as_deref().is_none_or(|part| email.contains(part));
+        let after = match (&self.after, self.order) {
+            (None, _) => true,
+            (Some(after), Order::Asc) => position > after,
+            (Some(after), Order::Desc) => position < after,
+        };
+        prefix && contains && after
+    }
+}
+
+impl Default for UserQuery {
+    fn default() -> Self {
+        Self {
+            limit: Self::DEFAULT_LIMIT,
+            sort: UserSort::default(),
+            order: Order::default(),
+            email_prefix: None,
+            email_contains: None,
+            after: None,
+        }
+    }
+}
+
+impl Cursor {
+    /// The position of `user`, created at `created_at`, when sorted by
+    /// `sort`.
+    pub fn of(sort: UserSort, user: &User, created_at: i64) -> Self {
+        match sort {
+            UserSort::Id => Cursor::Id(user.id),
+            UserSort::Email => Cursor::Email(user.email.clone(), user.id),
+            UserSort::CreatedAt => Cursor::CreatedAt(created_at, user.id),
+        }
+    }
+
+    pub fn sort(&self) -> UserSort {
+        match self {
+            Cursor::Id(_) => UserSort::Id,
+            Cursor::Email(..) => UserSort::Email,
+            Cursor::CreatedAt(..) => UserSort::CreatedAt,
+        }
+    }
+}
+
+impl Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let json = serde_json::to_vec(self).expect("Cursor Is Serializable");
+        write!(f, "{}", URL_SAFE_NO_PAD.encode(json))
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = InvalidCursor;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let json = URL_SAFE_NO_PAD.decode(s).map_err(|_| InvalidCursor)?;
+        serde_json::from_slice(&json).map_err(|_| InvalidCursor)
+    }
+}
+
+impl Page {
+    /// The page of the first `limit` users of `rows`, positioned and sorted
+    /// by the store; a store fetches one more than the `limit` to know
+    /// whether a next page follows.
+    pub fn new(mut rows: Vec<(Cursor, User)>, limit: usize) -> Self {
+        let next = match rows.len() > limit {
+            true => {
+                rows.truncate(limit);
+                rows.last().map(|(cursor, _)| cursor.clone())
+            }
+            false => None,
+        };
+        let users = rows.into_iter().map(|(_, user)| user).collect();
+        Self { users, next }
+    }
+}
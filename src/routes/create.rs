@@ -1,6 +1,8 @@
 use crate::error;
 use crate::error::ErrorBody;
 use crate::error::OpaqueError;
+use crate::middleware::cache::IdempotencyContext;
+use crate::obs::RequestSpan;
 use crate::service::ServiceError;
 use crate::service::SharedService;
 use crate::user::NewUser;
@@ -12,16 +14,27 @@ use axum::Json;
 use color_eyre::eyre;
 use hyper::StatusCode;
 use std::fmt::Debug;
+use std::sync::Arc;
 
 pub async fn create_user(
     service: Extension<SharedService>,
+    span: Extension<RequestSpan>,
+    context: Option<Extension<IdempotencyContext>>,
     Json(new_user): Json<NewUser>,
 ) -> Result<Json<User>, CreateUserError> {
     let mut service = service.write().await;
-    let res = service.create(&new_user).await;
+    let res = match context {
+        Some(Extension(context)) => {
+            let key = context.key.clone();
+            let render = Arc::new(move |user: &User| context.json_response(StatusCode::OK, user));
+            service.create_recorded(&new_user, &key, render).await
+        }
+        None => service.create(&new_user).await,
+    };
 
     match res {
         Ok(new_user) => {
+            span.record_user_id(new_user.id);
             tracing::info!("New User Created");
             Ok(Json(new_user))
         }
@@ -0,0 +1,115 @@
+use crate::error;
+use crate::error::ErrorBody;
+use crate::error::OpaqueError;
+use crate::middleware::cache::IdempotencyContext;
+use crate::obs::RequestSpan;
+use crate::service::ServiceError;
+use crate::service::SharedService;
+use crate::user::DeletedUser;
+
+use axum::extract::Path;
+use axum::extract::Query;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::Extension;
+use axum::Json;
+use color_eyre::eyre;
+use hyper::StatusCode;
+use serde::Deserialize;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeleteParams {
+    /// Removes the user, or its tombstone, for good.
+    pub purge: bool,
+}
+
+/// Deletes the user softly, answering with its tombstone; *otherwise*, given
+/// `?purge=true`, removes it for good, answering with no content.
+pub async fn delete_user(
+    Path(id): Path<String>,
+    Query(params): Query<DeleteParams>,
+    service: Extension<SharedService>,
+    span: Extension<RequestSpan>,
+    context: Option<Extension<IdempotencyContext>>,
+) -> Result<Response, DeleteUserError> {
+    span.record_user_id(&id);
+    let mut service = service.write().await;
+    let res = match (params.purge, context) {
+        (false, Some(Extension(context))) => {
+            let key = context.key.clone();
+            let render = Arc::new(move |deleted: &DeletedUser| {
+                context.json_response(StatusCode::OK, deleted)
+            });
+            service.delete_recorded(&id, &key, render).await.map(Some)
+        }
+        (false, None) => service.delete(&id).await.map(Some),
+        (true, Some(Extension(context))) => {
+            let response = context.empty_response(StatusCode::NO_CONTENT);
+            service.purge_recorded(&id, &context.key, &response).await.map(|_| None)
+        }
+        (true, None) => service.purge(&id).await.map(|_| None),
+    };
+
+    match res {
+        Ok(Some(deleted)) => {
+            tracing::info!("User Deleted");
+            Ok(Json(deleted).into_response())
+        }
+        Ok(None) => {
+            tracing::info!("User Purged");
+            Ok(StatusCode::NO_CONTENT.into_response())
+        }
+        Err(error) => {
+            tracing::info!("{:#?}", error);
+            Err(error.into())
+        }
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum DeleteUserError {
+    /// The user might be deleted already.
+    #[error("{0}")]
+    UserNotFound(#[source] ServiceError),
+    /// User id might be malformed.
+    #[error("{0}")]
+    Validation(#[source] ServiceError),
+    /// Internal (!Business & !Use-Case Errors)
+    #[error(transparent)]
+    Internal(#[from] OpaqueError),
+}
+
+impl IntoResponse for DeleteUserError {
+    fn into_response(self) -> axum::response::Response {
+        use DeleteUserError::*;
+        let (status, error) = match self {
+            UserNotFound(e) => (StatusCode::NOT_FOUND, e.to_string()),
+            Validation(e) => (StatusCode::BAD_REQUEST, e.to_string()),
+            Internal(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        };
+
+        let body = Json(ErrorBody { error });
+        (status, body).into_response()
+    }
+}
+
+impl From<ServiceError> for DeleteUserError {
+    fn from(value: ServiceError) -> Self {
+        match value {
+            ServiceError::UserNotFound(_) => Self::UserNotFound(value),
+            ServiceError::ValidationError(_) => Self::Validation(value),
+            // Deleting a user takes no email.
+            ServiceError::EmailTaken(_) => Self::Internal(eyre::eyre!(value)),
+            ServiceError::UnexpectedError(internal) => Self::Internal(eyre::eyre!(internal)),
+        }
+    }
+}
+
+impl Debug for DeleteUserError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error::get_error_cause(self, f)
+    }
+}
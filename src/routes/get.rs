@@ -1,5 +1,6 @@
 use crate::error::get_error_cause;
 use crate::error::ErrorBody;
+use crate::obs::RequestSpan;
 use crate::service::ServiceError;
 use crate::service::SharedService;
 use crate::user::User;
@@ -15,7 +16,9 @@ use std::fmt::Debug;
 pub async fn get_user(
     Path(key): Path<String>,
     service: Extension<SharedService>,
+    span: Extension<RequestSpan>,
 ) -> Result<Json<User>, GetUserErrors> {
+    span.record_user_id(&key);
     let db = service.read().await;
     let maybe = db.get(&key).await;
 
@@ -0,0 +1,68 @@
+//! Probes for the orchestrator.
+use crate::middleware::cache::handle::CacheHandle;
+use crate::service::SharedService;
+
+use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::Extension;
+use axum::Json;
+use hyper::StatusCode;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The outcome of a probe, with the outcome of each of its checks.
+#[derive(Debug, Serialize)]
+pub struct Probe {
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub checks: BTreeMap<&'static str, String>,
+}
+
+/// The process is up and serving requests.
+pub async fn healthz() -> Probe {
+    Probe::from_checks([])
+}
+
+/// The API can serve requests: the cache answers a ping, and the user store
+/// is reachable.
+#[tracing::instrument(name = "Checking Readiness", skip_all)]
+pub async fn readyz(cache: Extension<CacheHandle>, service: Extension<SharedService>) -> Probe {
+    let cache = cache.ping().await.map_err(|e| e.to_string());
+    let store = service.read().await.ping().await.map_err(|e| e.to_string());
+    Probe::from_checks([("cache", cache), ("store", store)])
+}
+
+/// The cache has not stopped or stalled; *otherwise* the process should be
+/// restarted. Unlike [readyz], it does not wait behind the requests, so that
+/// a busy process is not restarted.
+#[tracing::instrument(name = "Checking Liveness", skip_all)]
+pub async fn livez(cache: Extension<CacheHandle>) -> Probe {
+    let cache = cache.alive().await.map_err(|e| e.to_string());
+    Probe::from_checks([("cache", cache)])
+}
+
+impl Probe {
+    fn from_checks<const N: usize>(checks: [(&'static str, Result<(), String>); N]) -> Self {
+        let ok = checks.iter().all(|(_, check)| check.is_ok());
+        let checks = checks
+            .into_iter()
+            .map(|(name, check)| (name, check.err().unwrap_or_else(|| "ok".to_string())))
+            .collect();
+
+        if !ok {
+            tracing::error!("Probe Failed: {:#?}", checks);
+        }
+        let status = if ok { "ok" } else { "unavailable" };
+        Self { status, checks }
+    }
+}
+
+impl IntoResponse for Probe {
+    fn into_response(self) -> Response {
+        let status = match self.status {
+            "ok" => StatusCode::OK,
+            _ => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        (status, Json(self)).into_response()
+    }
+}
@@ -1,30 +1,56 @@
 use crate::error::get_error_cause;
 use crate::error::ErrorBody;
 use crate::error::OpaqueError;
+use crate::page::Cursor;
+use crate::page::ListParams;
+use crate::service::ServiceError;
 use crate::service::SharedService;
-use crate::user::User;
 
+use axum::extract::Query;
 use axum::response::IntoResponse;
+use axum::response::Response;
 use axum::Extension;
 use axum::Json;
-use color_eyre::eyre::Context;
+use color_eyre::eyre;
+use hyper::header;
+use hyper::http::HeaderValue;
 use hyper::StatusCode;
+use hyper::Uri;
 use std::fmt::Debug;
 
-type Users = Json<Vec<User>>;
-
+/// A page of users, with a `Link` to the next one when more users follow.
 #[tracing::instrument(name = "Get All Users", skip(service))]
-pub async fn get_users(service: Extension<SharedService>) -> Result<Users, ListUsersError> {
+pub async fn get_users(
+    uri: Uri,
+    Query(params): Query<ListParams>,
+    service: Extension<SharedService>,
+) -> Result<Response, ListUsersError> {
     let service = service.read().await;
-    tracing::info!("Attempting to get all users");
-    let mut users = service.list().await.context("Failed to get all users")?;
-    users.sort();
-    tracing::info!("All users fetched");
-    Ok(Json(users))
+    tracing::info!("Attempting to get a page of users");
+    let page = service.list(&params).await?;
+    tracing::info!("Page of users fetched");
+
+    let next = page.next.map(|cursor| next_link(uri.path(), &params, &cursor));
+    let mut response = Json(page.users).into_response();
+    if let Some(next) = next {
+        response.headers_mut().insert(header::LINK, next);
+    }
+    Ok(response)
+}
+
+/// The `rel="next"` link to the page after `cursor`, with the same `params`.
+fn next_link(path: &str, params: &ListParams, cursor: &Cursor) -> HeaderValue {
+    let params = ListParams { cursor: Some(cursor.to_string()), ..params.clone() };
+    let query = serde_urlencoded::to_string(&params).expect("Params Are Encodable");
+    HeaderValue::try_from(format!("<{path}?{query}>; rel=\"next\""))
+        .expect("Encoded Params Are Valid in a Header")
 }
 
 #[derive(thiserror::Error)]
 pub enum ListUsersError {
+    /// The limit or the cursor might be malformed.
+    #[error("{0}")]
+    Validation(#[source] ServiceError),
     #[error(transparent)]
     Unexpected(#[from] OpaqueError),
 }
@@ -32,6 +58,7 @@ pub enum ListUsersError {
 impl IntoResponse for ListUsersError {
     fn into_response(self) -> axum::response::Response {
         let (status, error) = match &self {
+            ListUsersError::Validation(error) => (StatusCode::BAD_REQUEST, error.to_string()),
             ListUsersError::Unexpected(error) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, error.to_string())
             }
@@ -41,6 +68,15 @@ impl IntoResponse for ListUsersError {
     }
 }
 
+impl From<ServiceError> for ListUsersError {
+    fn from(value: ServiceError) -> Self {
+        match value {
+            ServiceError::ValidationError(_) => Self::Validation(value),
+            otherwise => Self::Unexpected(eyre::eyre!(otherwise)),
+        }
+    }
+}
+
 impl Debug for ListUsersError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         get_error_cause(self, f)
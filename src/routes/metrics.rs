@@ -0,0 +1,17 @@
+use crate::metrics::Metrics;
+use crate::middleware::cache::handle::CacheHandle;
+
+use axum::response::IntoResponse;
+use axum::Extension;
+use hyper::header;
+use std::sync::Arc;
+
+/// Renders the [Metrics] for Prometheus to scrape.
+#[tracing::instrument(name = "Rendering Metrics", skip_all)]
+pub async fn metrics(
+    Extension(metrics): Extension<Arc<Metrics>>,
+    Extension(cache): Extension<CacheHandle>,
+) -> impl IntoResponse {
+    let body = metrics.render(&cache).await;
+    ([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], body)
+}
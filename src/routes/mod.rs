@@ -1,7 +1,18 @@
 mod create;
+mod delete;
 mod get;
+mod health;
 mod list;
+mod metrics;
+mod update;
 
 pub use create::create_user;
+pub use delete::delete_user;
 pub use get::get_user;
+pub use health::healthz;
+pub use health::livez;
+pub use health::readyz;
 pub use list::get_users;
+pub use metrics::metrics;
+pub use update::replace_user;
+pub use update::update_user;
@@ -0,0 +1,118 @@
+use crate::error;
+use crate::error::ErrorBody;
+use crate::error::OpaqueError;
+use crate::middleware::cache::IdempotencyContext;
+use crate::obs::RequestSpan;
+use crate::service::ServiceError;
+use crate::service::SharedService;
+use crate::user::NewUser;
+use crate::user::User;
+use crate::user::UserPatch;
+
+use axum::extract::Path;
+use axum::response::IntoResponse;
+use axum::Extension;
+use axum::Json;
+use color_eyre::eyre;
+use hyper::StatusCode;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// Changes the fields of the user given in the body.
+pub async fn update_user(
+    Path(id): Path<String>,
+    service: Extension<SharedService>,
+    span: Extension<RequestSpan>,
+    context: Option<Extension<IdempotencyContext>>,
+    Json(patch): Json<UserPatch>,
+) -> Result<Json<User>, UpdateUserError> {
+    update(&id, &patch, service, span, context).await
+}
+
+/// Replaces every field of the user with the body.
+pub async fn replace_user(
+    Path(id): Path<String>,
+    service: Extension<SharedService>,
+    span: Extension<RequestSpan>,
+    context: Option<Extension<IdempotencyContext>>,
+    Json(user): Json<NewUser>,
+) -> Result<Json<User>, UpdateUserError> {
+    update(&id, &UserPatch::from(user), service, span, context).await
+}
+
+async fn update(
+    id: &str,
+    patch: &UserPatch,
+    service: Extension<SharedService>,
+    span: Extension<RequestSpan>,
+    context: Option<Extension<IdempotencyContext>>,
+) -> Result<Json<User>, UpdateUserError> {
+    span.record_user_id(id);
+    let mut service = service.write().await;
+    let res = match context {
+        Some(Extension(context)) => {
+            let key = context.key.clone();
+            let render = Arc::new(move |user: &User| context.json_response(StatusCode::OK, user));
+            service.update_recorded(id, patch, &key, render).await
+        }
+        None => service.update(id, patch).await,
+    };
+
+    match res {
+        Ok(user) => {
+            tracing::info!("User Updated");
+            Ok(Json(user))
+        }
+        Err(error) => {
+            tracing::info!("{:#?}", error);
+            Err(error.into())
+        }
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum UpdateUserError {
+    #[error("{0}")]
+    UserNotFound(#[source] ServiceError),
+    /// Email might be taken.
+    #[error("{0}")]
+    EmailTaken(#[source] ServiceError),
+    /// Email or user id might be malformed.
+    #[error("{0}")]
+    Validation(#[source] ServiceError),
+    /// Internal (!Business & !Use-Case Errors)
+    #[error(transparent)]
+    Internal(#[from] OpaqueError),
+}
+
+impl IntoResponse for UpdateUserError {
+    fn into_response(self) -> axum::response::Response {
+        use UpdateUserError::*;
+        let (status, error) = match self {
+            UserNotFound(e) => (StatusCode::NOT_FOUND, e.to_string()),
+            EmailTaken(e) => (StatusCode::CONFLICT, e.to_string()),
+            Validation(e) => (StatusCode::BAD_REQUEST, e.to_string()),
+            Internal(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        };
+
+        let body = Json(ErrorBody { error });
+        (status, body).into_response()
+    }
+}
+
+impl From<ServiceError> for UpdateUserError {
+    fn from(value: ServiceError) -> Self {
+        match value {
+            ServiceError::UserNotFound(_) => Self::UserNotFound(value),
+            ServiceError::EmailTaken(_) => Self::EmailTaken(value),
+            ServiceError::ValidationError(_) => Self::Validation(value),
+            ServiceError::UnexpectedError(internal) => Self::Internal(eyre::eyre!(internal)),
+        }
+    }
+}
+
+impl Debug for UpdateUserError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error::get_error_cause(self, f)
+    }
+}
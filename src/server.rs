@@ -1,82 +1,199 @@
-use crate::middleware::cache;
-use crate::middleware::cache::handle::CacheHandle;
-use crate::middleware::cache::manager::CacheManager;
+use crate::metrics::Metrics;
+use crate::middleware;
+use crate::middleware::cache::manager::ShardedCacheManager;
+use crate::middleware::cache::IdempotencyConfig;
+use crate::middleware::cache::IdempotencyLayer;
+use crate::obs;
 use crate::routes;
 use crate::service::Service;
 use crate::service::SharedService;
-use crate::warehouse::UserRepository;
+use crate::warehouse::Cache;
+use crate::warehouse::DynStore;
+use crate::warehouse::IdempotencyStore;
+use crate::warehouse::UserStore;
 use crate::ServerResult;
 
+use axum::extract::DefaultBodyLimit;
 use axum::handler::Handler;
-use axum::middleware;
 use axum::routing::get;
 use axum::Extension;
 use axum::Router;
 use axum::Server;
+use std::future::Future;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpListener;
-use tokio::sync::mpsc;
+use tokio::sync::oneshot;
 use tokio::sync::RwLock;
 use tower::ServiceBuilder;
+use tower_http::request_id::MakeRequestUuid;
+use tower_http::request_id::PropagateRequestIdLayer;
+use tower_http::request_id::SetRequestIdLayer;
 use tower_http::trace::TraceLayer;
 
-/// In a real implementation, this would be a connection pool:
-type ConnectionPool = UserRepository;
+/// How long in-flight requests may take to complete once shutdown begins.
+pub const DEFAULT_DRAIN: Duration = Duration::from_secs(30);
 
 pub struct UserApi {
     pub addr: SocketAddr,
+    listener: TcpListener,
     pub api: Router,
-    pub cache_manager: CacheManager,
+    pub cache_manager: ShardedCacheManager<DynStore>,
+    pub drain: Duration,
 }
 
 impl UserApi {
     /// Initialize a new [UserApi].
-    pub fn new(addr: TcpListener, pool: ConnectionPool) -> Self {
-        tracing::debug!(".. Configuring the API");
+    pub fn new(addr: TcpListener, pool: impl UserStore) -> Self {
+        Self::with_idempotency(addr, pool, IdempotencyConfig::default())
+    }
 
-        let (cache_handle, cache_manager) = {
-            let (sender, receiver) = mpsc::channel(8);
-            let cache_handle = CacheHandle::new(sender);
-            let cache_manager = CacheManager::new(receiver);
-            (cache_handle, cache_manager)
-        };
+    /// Initialize a new [UserApi] with the given [IdempotencyConfig], caching
+    /// in memory.
+    pub fn with_idempotency(
+        addr: TcpListener,
+        pool: impl UserStore,
+        config: IdempotencyConfig,
+    ) -> Self {
+        let shard = config.cache.shard(config.shards);
+        Self::with_stores(addr, pool, config, |_| Cache::with_config(&shard))
+    }
+
+    /// Initialize a new [UserApi] with the given [IdempotencyConfig], caching
+    /// shard `i` in the store `make(i)`; see [IdempotencyLayer::with_stores].
+    pub fn with_stores<S: IdempotencyStore>(
+        addr: TcpListener,
+        pool: impl UserStore,
+        config: IdempotencyConfig,
+        mut make: impl FnMut(usize) -> S,
+    ) -> Self {
+        tracing::debug!(".. Configuring the API");
+        let make = |i| Box::new(make(i)) as DynStore;
+        let (idempotency, cache_manager) = IdempotencyLayer::with_stores(config, make);
 
         tracing::info!(".. the API was configured successfully");
-        let api = Self::router(cache_handle, pool);
-        let addr = addr.local_addr().expect("Port was Bound");
-        Self { addr, api, cache_manager }
+        let api = Self::router(idempotency, pool);
+        let listener = addr;
+        let addr = listener.local_addr().expect("Port was Bound");
+        Self { addr, listener, api, cache_manager, drain: DEFAULT_DRAIN }
     }
 
-    pub fn router(cache_handle: CacheHandle, pool: UserRepository) -> Router {
-        let tracing = TraceLayer::new_for_http();
+    pub fn router(idempotency: IdempotencyLayer, pool: impl UserStore) -> Router {
+        let body_limit = DefaultBodyLimit::max(idempotency.config().body_limit);
+        let cache = idempotency.cache().clone();
+        let metrics = Arc::new(Metrics::new(idempotency.metrics()));
+        let tracing = TraceLayer::new_for_http().make_span_with(obs::request_span);
         let user_service: SharedService = Arc::new(RwLock::new(Service::new(pool)));
         let service = ServiceBuilder::new()
+            .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+            .layer(PropagateRequestIdLayer::x_request_id())
             .layer(tracing)
-            .layer(Extension(cache_handle))
-            .layer(Extension(user_service));
+            .layer(axum::middleware::from_fn(middleware::span::scope))
+            .layer(body_limit)
+            .layer(Extension(user_service))
+            .layer(Extension(cache))
+            .layer(Extension(metrics));
 
-        let post_user = routes::create_user.layer(middleware::from_fn(cache::process));
+        let post_user = routes::create_user.layer(idempotency.clone());
+        let put_user = routes::replace_user.layer(idempotency.clone());
+        let patch_user = routes::update_user.layer(idempotency.clone());
+        let delete_user = routes::delete_user.layer(idempotency);
         let get_users = routes::get_users;
         let get_user = routes::get_user;
 
         Router::new()
             .route("/users", get(get_users).post(post_user))
-            .route("/users/:id", get(get_user))
+            .route("/users/:id", get(get_user).put(put_user).patch(patch_user).delete(delete_user))
+            .route("/healthz", get(routes::healthz))
+            .route("/readyz", get(routes::readyz))
+            .route("/livez", get(routes::livez))
+            .route("/metrics", get(routes::metrics))
+            .route_layer(axum::middleware::from_fn(middleware::metrics::track))
             .layer(service)
     }
 
+    /// Serves the API until SIGINT or SIGTERM is received.
     pub async fn run(self) -> ServerResult<()> {
+        self.run_until(shutdown_signal()).await
+    }
+
+    /// Serves the API until `shutdown` completes, then gives in-flight
+    /// requests up to [UserApi::drain] to finish before stopping the
+    /// [ShardedCacheManager], which releases the keys of the requests dropped
+    /// unfinished.
+    pub async fn run_until(
+        self,
+        shutdown: impl Future<Output = ()> + Send + 'static,
+    ) -> ServerResult<()> {
         use color_eyre::eyre::WrapErr;
 
+        let (stop, stopped) = oneshot::channel::<()>();
         let mut mngr = self.cache_manager;
-        tokio::spawn(async move { mngr.run().await });
+        let manager = tokio::spawn(async move {
+            mngr.run_until(async {
+                let _ = stopped.await;
+            })
+            .await
+        });
         tracing::warn!("CacheManager Spawned");
 
-        let server = Server::bind(&self.addr);
+        // Serve from the bound listener, so that connections are accepted as
+        // soon as the port is bound.
+        let listener = self.listener.into_std().context("Listener Conversion Failed")?;
+        let server = Server::from_tcp(listener).context("Server Creation Failed")?;
         tracing::info!(".. Serving API @ {}", self.addr);
 
+        let (draining, drain_started) = oneshot::channel::<()>();
         let api = self.api.into_make_service();
-        server.serve(api).await.context("Server Creation Failed")
+        let serving = server.serve(api).with_graceful_shutdown(async move {
+            shutdown.await;
+            tracing::warn!("Shutdown Signal Received, Draining Requests");
+            let _ = draining.send(());
+        });
+
+        let drain = self.drain;
+        let drained = async move {
+            match drain_started.await {
+                Ok(()) => tokio::time::sleep(drain).await,
+                Err(_) => std::future::pending().await,
+            }
+        };
+
+        let served = tokio::select! {
+            res = serving => res.context("Server Failed"),
+            _ = drained => {
+                tracing::error!("Drain Period Elapsed, Dropping In-Flight Requests");
+                Ok(())
+            }
+        };
+
+        let _ = stop.send(());
+        manager.await.context("CacheManager Failed")?;
+        tracing::warn!(".. API Stopped");
+        served
+    }
+}
+
+/// Completes when the process receives SIGINT (Ctrl+C) or SIGTERM.
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("Ctrl+C Handler Installed");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::signal;
+        use tokio::signal::unix::SignalKind;
+
+        signal(SignalKind::terminate()).expect("SIGTERM Handler Installed").recv().await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
     }
 }
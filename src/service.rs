@@ -1,52 +1,257 @@
 use crate::error::get_error_cause;
 use crate::error::OpaqueError;
+use crate::ikey::IKey;
+use crate::obs;
+use crate::page::Cursor;
+use crate::page::ListParams;
+use crate::page::Page;
+use crate::page::UserQuery;
+use crate::user::DeletedUser;
 use crate::user::NewUser;
 use crate::user::User;
+use crate::user::UserPatch;
+use crate::warehouse::CachedResponse;
+use crate::warehouse::Render;
 use crate::warehouse::UserRepoError;
-use crate::warehouse::UserRepository;
+use crate::warehouse::UserStore;
 
 use color_eyre::eyre::Context;
 use std::fmt::Debug;
 use std::sync::Arc;
+use std::sync::PoisonError;
 use tokio::sync::RwLock;
 
 pub type SharedService = Arc<RwLock<Service>>;
 
-#[derive(Clone, Debug)]
+/// The [User]s business rules, over a [UserStore].
+///
+/// The store is called from the blocking pool of the runtime, since a store
+/// backed by a database blocks while waiting for it.
+#[derive(Debug)]
 pub struct Service {
-    pub db: UserRepository,
+    db: Arc<std::sync::RwLock<Box<dyn UserStore>>>,
 }
 
 impl Service {
-    pub fn new(db: UserRepository) -> Self {
-        Self { db }
+    pub fn new(db: impl UserStore) -> Self {
+        Self { db: Arc::new(std::sync::RwLock::new(Box::new(db))) }
     }
 
     #[tracing::instrument]
     pub async fn create(&mut self, new_user: &NewUser) -> Result<User, ServiceError> {
         Self::validate_email(&new_user.email)?;
         tracing::info!("Email Validated");
-        let user = self.db.create(new_user).map_err(ServiceError::EmailTaken)?;
+        let new_user = new_user.clone();
+        let user =
+            self.write(move |db| db.create(&new_user)).await.map_err(ServiceError::EmailTaken)?;
+        tracing::info!("User Created");
+        Ok(user)
+    }
+
+    /// Creates a [User] like [Service::create], recording the response
+    /// `render`ed for it under `key` in the same transaction.
+    #[tracing::instrument(skip(render))]
+    pub async fn create_recorded(
+        &mut self,
+        new_user: &NewUser,
+        key: &IKey,
+        render: Arc<Render<User>>,
+    ) -> Result<User, ServiceError> {
+        Self::validate_email(&new_user.email)?;
+        tracing::info!("Email Validated");
+        let (new_user, key) = (new_user.clone(), key.clone());
+        let user = self
+            .write(move |db| db.create_recorded(&new_user, &key, &*render))
+            .await
+            .map_err(ServiceError::EmailTaken)?;
         tracing::info!("User Created");
         Ok(user)
     }
 
     #[tracing::instrument]
     pub async fn get(&self, id: &str) -> Result<User, ServiceError> {
-        let id = id
-            .parse::<u64>()
-            .map_err(|e| ServiceError::ValidationError(format!("{e} is not a valid user id")))?;
-        tracing::info!("ID Validated");
-        let user = self.db.get(id).map_err(ServiceError::UserNotFound)?;
+        let id = Self::parse_id(id)?;
+        let user = self.read(move |db| db.get(id)).await.map_err(ServiceError::UserNotFound)?;
         tracing::info!("User {} Fetched", user.id);
         Ok(user)
     }
 
+    /// Changes the fields of the [User] with `id` given in the `patch`,
+    /// keeping its email unique.
+    #[tracing::instrument]
+    pub async fn update(&mut self, id: &str, patch: &UserPatch) -> Result<User, ServiceError> {
+        let user = self.patched(id, patch).await?;
+        let user = self.write(move |db| db.update(&user)).await.map_err(Self::repo_error)?;
+        tracing::info!("User Updated");
+        Ok(user)
+    }
+
+    /// Updates a [User] like [Service::update], recording the response
+    /// `render`ed for it under `key` in the same transaction.
+    #[tracing::instrument(skip(render))]
+    pub async fn update_recorded(
+        &mut self,
+        id: &str,
+        patch: &UserPatch,
+        key: &IKey,
+        render: Arc<Render<User>>,
+    ) -> Result<User, ServiceError> {
+        let user = self.patched(id, patch).await?;
+        let key = key.clone();
+        let user = self
+            .write(move |db| db.update_recorded(&user, &key, &*render))
+            .await
+            .map_err(Self::repo_error)?;
+        tracing::info!("User Updated");
+        Ok(user)
+    }
+
+    /// Deletes the [User] with `id` softly, keeping its tombstone.
+    #[tracing::instrument]
+    pub async fn delete(&mut self, id: &str) -> Result<DeletedUser, ServiceError> {
+        let id = Self::parse_id(id)?;
+        let deleted = self.write(move |db| db.delete(id)).await.map_err(Self::repo_error)?;
+        tracing::info!("User Deleted");
+        Ok(deleted)
+    }
+
+    /// Deletes a [User] like [Service::delete], recording the response
+    /// `render`ed for its tombstone under `key` in the same transaction.
+    #[tracing::instrument(skip(render))]
+    pub async fn delete_recorded(
+        &mut self,
+        id: &str,
+        key: &IKey,
+        render: Arc<Render<DeletedUser>>,
+    ) -> Result<DeletedUser, ServiceError> {
+        let id = Self::parse_id(id)?;
+        let key = key.clone();
+        let deleted = self
+            .write(move |db| db.delete_recorded(id, &key, &*render))
+            .await
+            .map_err(Self::repo_error)?;
+        tracing::info!("User Deleted");
+        Ok(deleted)
+    }
+
+    /// Removes the [User] with `id`, or its tombstone, for good.
+    #[tracing::instrument]
+    pub async fn purge(&mut self, id: &str) -> Result<(), ServiceError> {
+        let id = Self::parse_id(id)?;
+        self.write(move |db| db.purge(id)).await.map_err(Self::repo_error)?;
+        tracing::info!("User Purged");
+        Ok(())
+    }
+
+    /// Purges a [User] like [Service::purge], recording the `response` under
+    /// `key` in the same transaction.
+    #[tracing::instrument(skip(response))]
+    pub async fn purge_recorded(
+        &mut self,
+        id: &str,
+        key: &IKey,
+        response: &CachedResponse,
+    ) -> Result<(), ServiceError> {
+        let id = Self::parse_id(id)?;
+        let (key, response) = (key.clone(), response.clone());
+        self.write(move |db| db.purge_recorded(id, &key, &response))
+            .await
+            .map_err(Self::repo_error)?;
+        tracing::info!("User Purged");
+        Ok(())
+    }
+
+    /// The page of users given by the `params`.
     #[tracing::instrument(skip(self))]
-    pub async fn list(&self) -> Result<Vec<User>, ServiceError> {
-        let users = self.db.list().context("Failed to Get All Users")?;
+    pub async fn list(&self, params: &ListParams) -> Result<Page, ServiceError> {
+        let query = Self::query(params)?;
+        tracing::info!("Query Validated");
+        let page = self.read(move |db| db.list(&query)).await.context("Failed to List Users")?;
         tracing::info!("Users Fetched");
-        Ok(users)
+        Ok(page)
+    }
+
+    /// Checks that the user store is reachable.
+    pub async fn ping(&self) -> Result<(), ServiceError> {
+        self.read(|db| db.ping()).await.context("User Store Is Unreachable")?;
+        Ok(())
+    }
+
+    /// Runs `work` reading the store on the blocking pool.
+    async fn read<T: Send + 'static>(
+        &self,
+        work: impl FnOnce(&dyn UserStore) -> Result<T, UserRepoError> + Send + 'static,
+    ) -> Result<T, UserRepoError> {
+        let db = self.db.clone();
+        obs::spawn_blocking(move || work(&**db.read().unwrap_or_else(PoisonError::into_inner)))
+            .await
+            .context("User Store Task Failed")?
+    }
+
+    /// Runs `work` changing the store on the blocking pool.
+    async fn write<T: Send + 'static>(
+        &self,
+        work: impl FnOnce(&mut dyn UserStore) -> Result<T, UserRepoError> + Send + 'static,
+    ) -> Result<T, UserRepoError> {
+        let db = self.db.clone();
+        obs::spawn_blocking(move || work(&mut **db.write().unwrap_or_else(PoisonError::into_inner)))
+            .await
+            .context("User Store Task Failed")?
+    }
+
+    /// The [User] with `id`, with the `patch` applied and validated.
+    async fn patched(&self, id: &str, patch: &UserPatch) -> Result<User, ServiceError> {
+        let user = patch.apply(self.get(id).await?);
+        Self::validate_email(&user.email)?;
+        tracing::info!("Email Validated");
+        Ok(user)
+    }
+
+    fn query(params: &ListParams) -> Result<UserQuery, ServiceError> {
+        let limit = params.limit.unwrap_or(UserQuery::DEFAULT_LIMIT);
+        if !(1..=UserQuery::MAX_LIMIT).contains(&limit) {
+            let error = format!("limit must be between 1 and {}", UserQuery::MAX_LIMIT);
+            return Err(ServiceError::ValidationError(error));
+        }
+
+        let after = match &params.cursor {
+            Some(cursor) => Some(cursor.parse::<Cursor>().map_err(|_| {
+                ServiceError::ValidationError(format!("{cursor} is not a valid cursor"))
+            })?),
+            None => None,
+        };
+        // A cursor is only a position in the order it was given in.
+        if after.as_ref().is_some_and(|after| after.sort() != params.sort) {
+            let error = format!("cursor is not of a page sorted by {:?}", params.sort);
+            return Err(ServiceError::ValidationError(error));
+        }
+
+        Ok(UserQuery {
+            limit,
+            sort: params.sort,
+            order: params.order,
+            email_prefix: params.email_prefix.clone(),
+            email_contains: params.email_contains.clone(),
+            after,
+        })
+    }
+
+    fn parse_id(id: &str) -> Result<u64, ServiceError> {
+        let id = id
+            .parse::<u64>()
+            .map_err(|e| ServiceError::ValidationError(format!("{e} is not a valid user id")))?;
+        tracing::info!("ID Validated");
+        Ok(id)
+    }
+
+    /// Maps the errors of a store changing a user: it may have been removed,
+    /// or the email taken, since it was fetched.
+    fn repo_error(error: UserRepoError) -> ServiceError {
+        match error {
+            UserRepoError::UserNotFound(_) => ServiceError::UserNotFound(error),
+            UserRepoError::EmailTaken(_) => ServiceError::EmailTaken(error),
+            UserRepoError::Internal(report) => ServiceError::UnexpectedError(report),
+        }
     }
 
     fn validate_email(email: &str) -> Result<(), ServiceError> {
@@ -3,7 +3,7 @@ use std::fmt::Display;
 use serde::Deserialize;
 use serde::Serialize;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct NewUser {
     pub email: String,
 }
@@ -14,6 +14,28 @@ impl NewUser {
     }
 }
 
+/// The fields of a [User] to change; the others are left as they are.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserPatch {
+    pub email: Option<String>,
+}
+
+impl UserPatch {
+    /// The `user` with the fields of this patch changed.
+    pub fn apply(&self, user: User) -> User {
+        let email = self.email.clone().unwrap_or(user.email);
+        User { email, ..user }
+    }
+}
+
+/// A replacement of every field of a [User].
+impl From<NewUser> for UserPatch {
+    fn from(user: NewUser) -> Self {
+        Self { email: Some(user.email) }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct User {
     pub id: u64,
@@ -26,6 +48,15 @@ impl User {
     }
 }
 
+/// The tombstone of a [User] deleted softly: hidden, but kept.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct DeletedUser {
+    #[serde(flatten)]
+    pub user: User,
+    /// Milliseconds since the Unix epoch.
+    pub deleted_at: i64,
+}
+
 impl Display for User {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}: {}", self.id, self.email)
@@ -1,49 +1,343 @@
 use crate::error::get_error_cause;
+use crate::fingerprint::Fingerprint;
 use crate::ikey::IKey;
-use crate::user::User;
+use crate::warehouse::IdempotencyStore;
 
+use axum::body::boxed;
+use axum::body::Body;
+use axum::http::header;
+use axum::http::response::Parts;
+use axum::http::HeaderMap;
+use axum::http::HeaderName;
 use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::response::Response;
 use color_eyre::Report;
+use hyper::body::Bytes;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::fmt::Display;
+use std::time::Duration;
+use tokio::time::Instant;
 
-/// A response cache, mapping client provided [IKey] to [CachedResponse].
-#[derive(Clone, Debug, Default)]
-pub struct Cache(HashMap<IKey, CachedResponse>);
+/// A response cache, mapping client provided [IKey] to [CacheEntry].
+///
+/// Entries are retained for [CacheConfig::ttl] after they were created; an
+/// expired entry is treated as a miss until it is swept by [Cache::expire].
+///
+/// At most [CacheConfig::capacity] entries are held, after which the least
+/// recently used entries are evicted.
+#[derive(Clone, Debug)]
+pub struct Cache {
+    entries: HashMap<IKey, Stored>,
+    /// Keys ordered from the least to the most recently used.
+    recency: BTreeMap<u64, IKey>,
+    tick: u64,
+    evictions: u64,
+    ttl: Duration,
+    capacity: usize,
+}
+
+/// Configures the retention of the [Cache].
+#[derive(Clone, Debug)]
+pub struct CacheConfig {
+    /// How long an entry is retained after it was created.
+    pub ttl: Duration,
+    /// How often expired entries are swept from the cache.
+    pub sweep_every: Duration,
+    /// The maximum number of entries in the cache.
+    pub capacity: usize,
+}
+
+/// A [CacheEntry] with the time it was created at, and the tick it was last
+/// used at.
+#[derive(Clone, Debug)]
+struct Stored {
+    entry: CacheEntry,
+    created_at: Instant,
+    used: u64,
+}
+
+/// The state of a request made with an [IKey].
+#[derive(Clone, Debug)]
+pub enum CacheEntry {
+    /// The request is being processed by the handler.
+    Started(Fingerprint),
+    /// The request was processed and its response cached.
+    Completed(CachedResponse),
+}
+
+/// Outcome of trying to [lock](Cache::lock) an [IKey].
+#[derive(Clone, Debug)]
+pub enum Lock {
+    /// The key was free; the caller owns it until it calls `set` or `delete`.
+    Acquired,
+    /// Another request with the same key is still being processed.
+    InFlight,
+    /// A request with the same key was already processed.
+    Completed(CachedResponse),
+    /// A *different* request was made with the same key.
+    Mismatch,
+}
 
 #[derive(Clone, Debug)]
 pub struct CachedResponse {
     pub status: StatusCode,
-    pub user: User,
+    /// The [CachedResponse::HEADERS] of the response.
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    pub fingerprint: Fingerprint,
 }
 
 #[derive(thiserror::Error)]
 pub enum CacheError {
     #[error("Cache for {0}")]
     CacheMiss(String),
+    #[error("Cache Is Full With {0} Requests In Flight")]
+    Full(usize),
     #[error(transparent)]
     Unexpected(#[from] Report),
 }
 
 impl Cache {
     pub fn new() -> Self {
-        Self(HashMap::default())
+        Self::with_config(&CacheConfig::default())
     }
 
-    /// Given a [IKey] and [CachedResponse], performs an upsert.
-    pub async fn set(&mut self, key: &IKey, res: &CachedResponse) -> Result<(), CacheError> {
-        let res = res.clone();
-        let key = key.clone();
-        self.0.insert(key, res);
+    pub fn with_config(config: &CacheConfig) -> Self {
+        Self {
+            entries: HashMap::default(),
+            recency: BTreeMap::default(),
+            tick: 0,
+            evictions: 0,
+            ttl: config.ttl,
+            capacity: config.capacity,
+        }
+    }
+
+    /// The number of entries in the cache, including expired ones.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the [CacheEntry] for `key`, unless it has expired.
+    fn live(&self, key: &IKey) -> Option<&CacheEntry> {
+        self.entries.get(key).filter(|stored| !self.is_expired(stored)).map(|stored| &stored.entry)
+    }
+
+    fn is_expired(&self, stored: &Stored) -> bool {
+        stored.created_at.elapsed() >= self.ttl
+    }
+
+    /// Upserts `entry` for `key` as the most recently used, evicting the least
+    /// recently used entries when a new `key` would exceed the capacity.
+    fn insert(
+        &mut self,
+        key: &IKey,
+        entry: CacheEntry,
+        created_at: Instant,
+    ) -> Result<(), CacheError> {
+        if !self.entries.contains_key(key) {
+            self.make_room()?;
+        }
+        self.remove(key);
+        let used = self.next_tick();
+        self.recency.insert(used, key.clone());
+        self.entries.insert(key.clone(), Stored { entry, created_at, used });
+        Ok(())
+    }
+
+    /// Evicts the least recently used entries until there is room for one
+    /// more. Entries that are still being processed are never evicted, unless
+    /// they have expired.
+    fn make_room(&mut self) -> Result<(), CacheError> {
+        while self.entries.len() >= self.capacity {
+            let evictable = self.recency.values().find(|key| {
+                let stored = &self.entries[*key];
+                self.is_expired(stored) || matches!(stored.entry, CacheEntry::Completed(_))
+            });
+            let Some(key) = evictable.cloned() else {
+                return Err(CacheError::Full(self.capacity));
+            };
+            self.remove(&key);
+            self.evictions += 1;
+            tracing::debug!("Evicted {key}");
+        }
         Ok(())
     }
 
+    /// Marks `key` as the most recently used.
+    fn touch(&mut self, key: &IKey) {
+        let used = self.next_tick();
+        if let Some(stored) = self.entries.get_mut(key) {
+            self.recency.remove(&stored.used);
+            stored.used = used;
+            self.recency.insert(used, key.clone());
+        }
+    }
+
+    fn remove(&mut self, key: &IKey) {
+        if let Some(stored) = self.entries.remove(key) {
+            self.recency.remove(&stored.used);
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+#[axum::async_trait]
+impl IdempotencyStore for Cache {
+    /// Given a [IKey] and [CachedResponse], performs an upsert.
+    ///
+    /// This completes any [Lock] previously acquired for `key`; the entry is
+    /// retained from the time the [Lock] was acquired.
+    async fn set(&mut self, key: &IKey, res: &CachedResponse) -> Result<(), CacheError> {
+        let entry = CacheEntry::Completed(res.clone());
+        let created_at = match self.entries.get(key) {
+            Some(stored) if !self.is_expired(stored) => stored.created_at,
+            _ => Instant::now(),
+        };
+        self.insert(key, entry, created_at)
+    }
+
     /// Given an [IKey], either returns a [CachedResponse] on a cache hit, or
     /// [CacheError] on miss.
-    pub async fn get(&self, key: &IKey) -> Result<CachedResponse, CacheError> {
-        let res = self.0.get(key).ok_or(CacheError::CacheMiss(key.to_string()))?;
-        Ok(res.clone())
+    ///
+    /// A key that is still being processed, or has expired, is a miss.
+    async fn get(&mut self, key: &IKey) -> Result<CachedResponse, CacheError> {
+        match self.live(key) {
+            Some(CacheEntry::Completed(res)) => {
+                let res = res.clone();
+                self.touch(key);
+                Ok(res)
+            }
+            _ => Err(CacheError::CacheMiss(key.to_string())),
+        }
+    }
+
+    /// Given an [IKey], marks it as [Started](CacheEntry::Started) unless a
+    /// request with the same key was seen already.
+    ///
+    /// A request seen already with a different [Fingerprint] is a
+    /// [Mismatch](Lock::Mismatch).
+    async fn lock(&mut self, key: &IKey, fingerprint: &Fingerprint) -> Result<Lock, CacheError> {
+        let lock = match self.live(key) {
+            Some(entry) if entry.fingerprint() != fingerprint => Lock::Mismatch,
+            Some(CacheEntry::Started(_)) => Lock::InFlight,
+            Some(CacheEntry::Completed(res)) => Lock::Completed(res.clone()),
+            None => {
+                let entry = CacheEntry::Started(fingerprint.clone());
+                self.insert(key, entry, Instant::now())?;
+                return Ok(Lock::Acquired);
+            }
+        };
+        self.touch(key);
+        Ok(lock)
+    }
+
+    /// Removes `key` from the cache, releasing any [Lock] held on it.
+    async fn delete(&mut self, key: &IKey) -> Result<(), CacheError> {
+        self.remove(key);
+        Ok(())
+    }
+
+    /// Removes all expired entries, returning how many were removed.
+    async fn expire(&mut self) -> Result<usize, CacheError> {
+        let expired: Vec<IKey> = self
+            .entries
+            .iter()
+            .filter(|(_, stored)| self.is_expired(stored))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &expired {
+            self.remove(key);
+        }
+        Ok(expired.len())
+    }
+
+    async fn size(&mut self) -> Result<usize, CacheError> {
+        Ok(self.len())
+    }
+
+    fn evictions(&self) -> u64 {
+        self.evictions
+    }
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CacheConfig {
+    /// Entries are retained for a day by default.
+    pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+    /// Expired entries are swept every minute by default.
+    pub const DEFAULT_SWEEP_EVERY: Duration = Duration::from_secs(60);
+    /// At most this many entries are cached by default.
+    pub const DEFAULT_CAPACITY: usize = 10_000;
+
+    /// The config of each of `shards` caches that together hold
+    /// [CacheConfig::capacity] entries.
+    pub fn shard(&self, shards: usize) -> Self {
+        let capacity = self.capacity.div_ceil(shards.max(1));
+        Self { capacity, ..self.clone() }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Self::DEFAULT_TTL,
+            sweep_every: Self::DEFAULT_SWEEP_EVERY,
+            capacity: Self::DEFAULT_CAPACITY,
+        }
+    }
+}
+
+impl CacheEntry {
+    /// The [Fingerprint] of the request that created the entry.
+    pub fn fingerprint(&self) -> &Fingerprint {
+        match self {
+            CacheEntry::Started(fingerprint) => fingerprint,
+            CacheEntry::Completed(res) => &res.fingerprint,
+        }
+    }
+}
+
+impl CachedResponse {
+    /// The response headers that are cached along with the body.
+    pub const HEADERS: [HeaderName; 3] =
+        [header::CONTENT_TYPE, header::CONTENT_LANGUAGE, header::LOCATION];
+
+    /// The header marking a response as a replay of a [CachedResponse].
+    pub const REPLAYED_HEADER: &'static str = "Idempotent-Replayed";
+
+    /// Captures the status, [CachedResponse::HEADERS] and `body` of a response.
+    pub fn new(head: &Parts, body: Bytes, fingerprint: Fingerprint) -> Self {
+        let headers = Self::HEADERS
+            .iter()
+            .filter_map(|name| Some((name.clone(), head.headers.get(name)?.clone())))
+            .collect();
+        Self { status: head.status, headers, body, fingerprint }
+    }
+}
+
+impl IntoResponse for CachedResponse {
+    fn into_response(self) -> Response {
+        let mut response = Response::new(boxed(Body::from(self.body)));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers;
+        response
     }
 }
 
@@ -55,6 +349,17 @@ impl Debug for CacheError {
 
 impl Display for CachedResponse {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Cached ({}, {})", self.status, self.user.id)
+        write!(f, "Cached ({}, {} bytes)", self.status, self.body.len())
+    }
+}
+
+impl Display for Lock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Lock::Acquired => write!(f, "Acquired"),
+            Lock::InFlight => write!(f, "In Flight"),
+            Lock::Completed(res) => write!(f, "Completed: {res}"),
+            Lock::Mismatch => write!(f, "Mismatch"),
+        }
     }
 }
@@ -1,6 +1,14 @@
 use crate::error::get_error_cause;
+use crate::page::Cursor;
+use crate::page::Order;
+use crate::page::Page;
+use crate::page::UserQuery;
+use crate::user::DeletedUser;
 use crate::user::NewUser;
 use crate::user::User;
+use crate::warehouse::sqlite::now_millis;
+use crate::warehouse::EmailReuse;
+use crate::warehouse::UserStore;
 
 use color_eyre::Report;
 use std::collections::HashMap;
@@ -9,45 +17,118 @@ use std::fmt::Debug;
 type UserId = u64;
 
 #[derive(Clone, Debug, Default)]
-pub struct UserRepository(HashMap<UserId, User>);
+pub struct UserRepository {
+    users: HashMap<UserId, User>,
+    /// The tombstones of the users deleted softly.
+    deleted: HashMap<UserId, DeletedUser>,
+    /// Milliseconds since the Unix epoch.
+    created_at: HashMap<UserId, i64>,
+    /// The last id given, which is never given again, even once purged.
+    last_id: UserId,
+    email_reuse: EmailReuse,
+}
 
 impl UserRepository {
     pub fn new() -> Self {
-        Self(HashMap::default())
+        Self::default()
+    }
+
+    /// Sets when the emails of deleted users can be taken again.
+    pub fn with_email_reuse(self, email_reuse: EmailReuse) -> Self {
+        Self { email_reuse, ..self }
+    }
+
+    /// Ids are never reused, so that a purged user is not mistaken for
+    /// another.
+    fn next_id(&mut self) -> UserId {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    fn email_is_available(&self, new_email: &str) -> Result<(), UserRepoError> {
+        let mut users = self.users.values();
+        let taken = match self.email_reuse {
+            EmailReuse::OnDelete => users.any(|user| user.email == new_email),
+            EmailReuse::OnPurge => users
+                .chain(self.deleted.values().map(|deleted| &deleted.user))
+                .any(|user| user.email == new_email),
+        };
+        match taken {
+            true => Err(UserRepoError::EmailTaken(new_email.to_string())),
+            false => Ok(()),
+        }
     }
+}
 
+impl UserStore for UserRepository {
     /// Create a new [User] from [NewUser].
-    pub fn create(&mut self, new_user: &NewUser) -> Result<User, UserRepoError> {
+    fn create(&mut self, new_user: &NewUser) -> Result<User, UserRepoError> {
         self.email_is_available(&new_user.email)?;
         tracing::info!("Email Is Free");
-        let new_id = self.random_id();
+        let new_id = self.next_id();
         let new_user = User::new(new_id, new_user.email.to_owned());
         // This will always return ``None``:
-        self.0.insert(new_user.id, new_user.clone());
+        self.users.insert(new_user.id, new_user.clone());
+        self.created_at.insert(new_user.id, now_millis());
         tracing::info!("New User Inserted to DB");
         Ok(new_user)
     }
 
-    /// Get all users.
-    pub fn list(&self) -> Result<Vec<User>, UserRepoError> {
-        let users = self.0.values().cloned().collect();
-        Ok(users)
+    /// Replaces the [User] with the id of `user`.
+    fn update(&mut self, user: &User) -> Result<User, UserRepoError> {
+        let current = self.get(user.id)?;
+        if current.email != user.email {
+            self.email_is_available(&user.email)?;
+            tracing::info!("Email Is Free");
+        }
+        self.users.insert(user.id, user.clone());
+        tracing::info!("User Updated in DB");
+        Ok(user.clone())
     }
 
-    /// Returns [User] with id ``id``; *otherwise* `NotFound`.
-    pub fn get(&self, user_id: u64) -> Result<User, UserRepoError> {
-        self.0.get(&user_id).cloned().ok_or(UserRepoError::UserNotFound(user_id))
+    /// Moves the [User] with id `user_id` to the tombstones.
+    fn delete(&mut self, user_id: u64) -> Result<DeletedUser, UserRepoError> {
+        let user = self.users.remove(&user_id).ok_or(UserRepoError::UserNotFound(user_id))?;
+        let deleted = DeletedUser { user, deleted_at: now_millis() };
+        self.deleted.insert(user_id, deleted.clone());
+        tracing::info!("User Deleted in DB");
+        Ok(deleted)
     }
 
-    fn random_id(&self) -> UserId {
-        self.0.keys().max().map_or(1, |id| id + 1)
+    /// Removes the [User] with id `user_id`, or its tombstone.
+    fn purge(&mut self, user_id: u64) -> Result<(), UserRepoError> {
+        let purged =
+            self.users.remove(&user_id).is_some() | self.deleted.remove(&user_id).is_some();
+        if !purged {
+            return Err(UserRepoError::UserNotFound(user_id));
+        }
+        self.created_at.remove(&user_id);
+        tracing::info!("User Purged from DB");
+        Ok(())
     }
 
-    fn email_is_available(&self, new_email: &str) -> Result<(), UserRepoError> {
-        match self.0.values().any(|user| user.email == new_email) {
-            true => Err(UserRepoError::EmailTaken(new_email.to_string())),
-            false => Ok(()),
-        }
+    /// Sorts the users matching the `query` to page through them.
+    fn list(&self, query: &UserQuery) -> Result<Page, UserRepoError> {
+        let mut rows: Vec<(Cursor, User)> = self
+            .users
+            .values()
+            .map(|user| {
+                let created_at = self.created_at.get(&user.id).copied().unwrap_or_default();
+                (Cursor::of(query.sort, user, created_at), user.clone())
+            })
+            .filter(|(position, user)| query.matches(&user.email, position))
+            .collect();
+        rows.sort_by(|(a, _), (b, _)| match query.order {
+            Order::Asc => a.cmp(b),
+            Order::Desc => b.cmp(a),
+        });
+        rows.truncate(query.limit + 1);
+        Ok(Page::new(rows, query.limit))
+    }
+
+    /// Returns [User] with id ``id``; *otherwise* `NotFound`.
+    fn get(&self, user_id: u64) -> Result<User, UserRepoError> {
+        self.users.get(&user_id).cloned().ok_or(UserRepoError::UserNotFound(user_id))
     }
 }
 
@@ -1,10 +1,25 @@
 //! Types related to storage, repositories, caches etc.
 mod cache;
 mod db;
+mod sqlite;
+mod store;
 
 pub use db::UserRepoError;
 pub use db::UserRepository;
 
 pub use cache::Cache;
+pub use cache::CacheConfig;
+pub use cache::CacheEntry;
 pub use cache::CacheError;
 pub use cache::CachedResponse;
+pub use cache::Lock;
+
+pub use store::DynStore;
+pub use store::EmailReuse;
+pub use store::IdempotencyStore;
+pub use store::Render;
+pub use store::UserStore;
+
+pub use sqlite::Database;
+pub use sqlite::SqliteCache;
+pub use sqlite::SqliteUserRepository;
@@ -0,0 +1,294 @@
+use super::now_millis;
+use super::Database;
+use crate::error::OpaqueError;
+use crate::fingerprint::Fingerprint;
+use crate::ikey::IKey;
+use crate::warehouse::CacheConfig;
+use crate::warehouse::CacheError;
+use crate::warehouse::CachedResponse;
+use crate::warehouse::IdempotencyStore;
+use crate::warehouse::Lock;
+
+use axum::http::HeaderMap;
+use axum::http::HeaderName;
+use axum::http::HeaderValue;
+use axum::http::StatusCode;
+use color_eyre::eyre::Context;
+use rusqlite::params;
+use rusqlite::Connection;
+use rusqlite::OptionalExtension;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// An [IdempotencyStore] persisting the responses in a SQLite [Database].
+///
+/// Entries are retained for [CacheConfig::ttl]; the capacity is not bounded.
+/// Clones share the locks they hold, which are released together; see
+/// [IdempotencyStore::release]. Shards share the table, so they are served by
+/// [SqliteCache::shard]s.
+#[derive(Clone, Debug)]
+pub struct SqliteCache {
+    db: Database,
+    ttl: Duration,
+    /// Identifies the locks held by this cache and its clones.
+    owner: String,
+    /// Whether this cache sweeps and counts the table.
+    sweeps: bool,
+}
+
+/// A row of the `idempotency` table.
+struct Row {
+    fingerprint: String,
+    status: Option<u16>,
+    headers: Option<String>,
+    body: Option<Vec<u8>>,
+}
+
+impl SqliteCache {
+    pub fn new(db: Database, config: &CacheConfig) -> Self {
+        let owner = format!("{}-{}", std::process::id(), now_nanos());
+        Self { db, ttl: config.ttl, owner, sweeps: true }
+    }
+
+    /// The clone of this cache serving shard `i` of a
+    /// [ShardedCacheManager](crate::middleware::cache::manager::ShardedCacheManager):
+    /// only the first shard sweeps and counts the table shared by all, so
+    /// that it is not swept by every shard, nor counted once per shard.
+    pub fn shard(&self, i: usize) -> Self {
+        Self { sweeps: i == 0, ..self.clone() }
+    }
+
+    /// The [Database] the records are kept in.
+    pub(super) fn database(&self) -> &Database {
+        &self.db
+    }
+
+    /// Entries created before this are expired.
+    fn cutoff(&self) -> i64 {
+        now_millis() - self.ttl.as_millis() as i64
+    }
+}
+
+#[axum::async_trait]
+impl IdempotencyStore for SqliteCache {
+    async fn get(&mut self, key: &IKey) -> Result<CachedResponse, CacheError> {
+        let (key, cutoff) = (key.clone(), self.cutoff());
+        self.db
+            .run(move |conn| {
+                match live(conn, &key, cutoff)?.map(Row::into_response).transpose()? {
+                    Some(Some(res)) => Ok(res),
+                    _ => Err(CacheError::CacheMiss(key.to_string())),
+                }
+            })
+            .await
+    }
+
+    async fn set(&mut self, key: &IKey, res: &CachedResponse) -> Result<(), CacheError> {
+        let headers = headers_to_json(&res.headers)?;
+        let (key, res, cutoff) = (key.clone(), res.clone(), self.cutoff());
+        self.db
+            .run(move |conn| {
+                conn.execute(
+                    "INSERT INTO idempotency (key, fingerprint, status, headers, body, created_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                     ON CONFLICT (key) DO UPDATE SET
+                        fingerprint = excluded.fingerprint,
+                        status = excluded.status,
+                        headers = excluded.headers,
+                        body = excluded.body,
+                        created_at = CASE WHEN created_at > ?7 THEN created_at ELSE excluded.created_at END",
+                    params![
+                        key.as_ref(),
+                        res.fingerprint.as_ref(),
+                        res.status.as_u16(),
+                        headers,
+                        res.body.as_ref(),
+                        now_millis(),
+                        cutoff,
+                    ],
+                )
+                .context("Failed to Upsert Idempotency Record")?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn lock(&mut self, key: &IKey, fingerprint: &Fingerprint) -> Result<Lock, CacheError> {
+        let (key, fingerprint, cutoff) = (key.clone(), fingerprint.clone(), self.cutoff());
+        let owner = self.owner.clone();
+        self.db
+            .run(move |conn| {
+                let lock = match live(conn, &key, cutoff)? {
+                    Some(row) if row.fingerprint != fingerprint.as_ref() => Lock::Mismatch,
+                    Some(row) => match row.into_response()? {
+                        Some(res) => Lock::Completed(res),
+                        None => Lock::InFlight,
+                    },
+                    None => {
+                        conn.execute(
+                            "INSERT OR REPLACE INTO idempotency (key, fingerprint, created_at, owner)
+                             VALUES (?1, ?2, ?3, ?4)",
+                            params![key.as_ref(), fingerprint.as_ref(), now_millis(), owner],
+                        )
+                        .context("Failed to Insert Idempotency Lock")?;
+                        Lock::Acquired
+                    }
+                };
+                Ok(lock)
+            })
+            .await
+    }
+
+    async fn delete(&mut self, key: &IKey) -> Result<(), CacheError> {
+        let key = key.clone();
+        self.db
+            .run(move |conn| {
+                conn.execute("DELETE FROM idempotency WHERE key = ?1", params![key.as_ref()])
+                    .context("Failed to Delete Idempotency Record")?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn expire(&mut self) -> Result<usize, CacheError> {
+        if !self.sweeps {
+            return Ok(0);
+        }
+        let cutoff = self.cutoff();
+        self.db
+            .run(move |conn| {
+                let expired = conn
+                    .execute("DELETE FROM idempotency WHERE created_at <= ?1", params![cutoff])
+                    .context("Failed to Delete Expired Idempotency Records")?;
+                Ok(expired)
+            })
+            .await
+    }
+
+    async fn size(&mut self) -> Result<usize, CacheError> {
+        if !self.sweeps {
+            return Ok(0);
+        }
+        self.db
+            .run(|conn| {
+                let size = conn
+                    .query_row("SELECT COUNT(*) FROM idempotency", [], |row| row.get(0))
+                    .context("Failed to Count Idempotency Records")?;
+                Ok(size)
+            })
+            .await
+    }
+
+    async fn release(&mut self) -> Result<usize, CacheError> {
+        let owner = self.owner.clone();
+        self.db
+            .run(move |conn| {
+                let released = conn
+                    .execute(
+                        "DELETE FROM idempotency WHERE status IS NULL AND owner = ?1",
+                        params![owner],
+                    )
+                    .context("Failed to Release Idempotency Locks")?;
+                Ok(released)
+            })
+            .await
+    }
+}
+
+/// Returns the [Row] for `key` created after `cutoff`, if any.
+fn live(conn: &Connection, key: &IKey, cutoff: i64) -> Result<Option<Row>, CacheError> {
+    let row = conn
+        .query_row(
+            "SELECT fingerprint, status, headers, body FROM idempotency
+             WHERE key = ?1 AND created_at > ?2",
+            params![key.as_ref(), cutoff],
+            |row| {
+                Ok(Row {
+                    fingerprint: row.get(0)?,
+                    status: row.get(1)?,
+                    headers: row.get(2)?,
+                    body: row.get(3)?,
+                })
+            },
+        )
+        .optional()
+        .context("Failed to Select Idempotency Record")?;
+    Ok(row)
+}
+
+/// Records `res` under `key`, completing the lock on it without changing when
+/// it was created.
+pub(super) fn record(
+    conn: &Connection,
+    key: &IKey,
+    res: &CachedResponse,
+) -> Result<(), OpaqueError> {
+    let headers = headers_to_json(&res.headers)?;
+    conn.execute(
+        "INSERT INTO idempotency (key, fingerprint, status, headers, body, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT (key) DO UPDATE SET
+            fingerprint = excluded.fingerprint,
+            status = excluded.status,
+            headers = excluded.headers,
+            body = excluded.body",
+        params![
+            key.as_ref(),
+            res.fingerprint.as_ref(),
+            res.status.as_u16(),
+            headers,
+            res.body.as_ref(),
+            now_millis(),
+        ],
+    )
+    .context("Failed to Record Idempotency Record")?;
+    Ok(())
+}
+
+/// Removes the records of requests still in flight, whose locks would
+/// otherwise be held until they expire.
+pub(super) fn release_in_flight(conn: &Connection) -> Result<usize, OpaqueError> {
+    let released = conn
+        .execute("DELETE FROM idempotency WHERE status IS NULL", [])
+        .context("Failed to Release In-Flight Idempotency Records")?;
+    Ok(released)
+}
+
+/// Nanoseconds since the Unix epoch, telling apart the caches of a process.
+fn now_nanos() -> u128 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    now.as_nanos()
+}
+
+fn headers_to_json(headers: &HeaderMap) -> Result<String, OpaqueError> {
+    let headers: Vec<(&str, &str)> = headers
+        .iter()
+        .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?)))
+        .collect();
+    serde_json::to_string(&headers).context("Failed to Serialize Headers")
+}
+
+impl Row {
+    /// The [CachedResponse] of a completed request; `None` while in flight.
+    fn into_response(self) -> Result<Option<CachedResponse>, CacheError> {
+        let (Some(status), Some(headers), Some(body)) = (self.status, self.headers, self.body)
+        else {
+            return Ok(None);
+        };
+
+        let status = StatusCode::from_u16(status).context("Invalid Cached Status")?;
+        let headers: Vec<(String, String)> =
+            serde_json::from_str(&headers).context("Failed to Deserialize Headers")?;
+        let headers: HeaderMap = headers
+            .into_iter()
+            .filter_map(|(name, value)| {
+                let name = HeaderName::try_from(name).ok()?;
+                let value = HeaderValue::try_from(value).ok()?;
+                Some((name, value))
+            })
+            .collect();
+        let fingerprint = Fingerprint(self.fingerprint);
+        Ok(Some(CachedResponse { status, headers, body: body.into(), fingerprint }))
+    }
+}
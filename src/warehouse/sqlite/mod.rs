@@ -0,0 +1,162 @@
+//! SQLite backed storage, persisting across restarts.
+use crate::error::OpaqueError;
+use crate::obs;
+
+use color_eyre::eyre::Context;
+use rusqlite::Connection;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+mod cache;
+mod users;
+
+pub use cache::SqliteCache;
+pub use users::SqliteUserRepository;
+
+/// The schema migrations, applied in order. The number of applied migrations
+/// is tracked in `PRAGMA user_version`.
+const MIGRATIONS: &[&str] = &[
+    // 1. Idempotency records; `status` is `NULL` while the request is in flight.
+    "CREATE TABLE idempotency (
+        key TEXT PRIMARY KEY NOT NULL,
+        fingerprint TEXT NOT NULL,
+        status INTEGER,
+        headers TEXT,
+        body BLOB,
+        created_at INTEGER NOT NULL
+    );",
+    // 2. Users; emails are unique.
+    "CREATE TABLE users (
+        id INTEGER PRIMARY KEY NOT NULL,
+        email TEXT NOT NULL UNIQUE
+    );",
+    // 3. Tombstones; `deleted_at` is set when a user is deleted softly, and
+    // only the emails of the other users are unique.
+    "CREATE TABLE users_tombstones (
+        id INTEGER PRIMARY KEY NOT NULL,
+        email TEXT NOT NULL,
+        deleted_at INTEGER
+    );
+    INSERT INTO users_tombstones (id, email) SELECT id, email FROM users;
+    DROP TABLE users;
+    ALTER TABLE users_tombstones RENAME TO users;
+    CREATE UNIQUE INDEX users_email ON users (email) WHERE deleted_at IS NULL;",
+    // 4. Creation times, `0` for the users created before; and the orders
+    // users are listed in.
+    "ALTER TABLE users ADD COLUMN created_at INTEGER NOT NULL DEFAULT 0;
+    CREATE INDEX users_by_email ON users (email, id) WHERE deleted_at IS NULL;
+    CREATE INDEX users_by_created_at ON users (created_at, id) WHERE deleted_at IS NULL;",
+    // 5. The [SqliteCache] holding the lock of a request in flight.
+    "ALTER TABLE idempotency ADD COLUMN owner TEXT;",
+    // 6. Ids are never reused, even once the user with the highest one is
+    // purged.
+    "CREATE TABLE users_autoincrement (
+        id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+        email TEXT NOT NULL,
+        deleted_at INTEGER,
+        created_at INTEGER NOT NULL DEFAULT 0
+    );
+    INSERT INTO users_autoincrement (id, email, deleted_at, created_at)
+        SELECT id, email, deleted_at, created_at FROM users;
+    DROP TABLE users;
+    ALTER TABLE users_autoincrement RENAME TO users;
+    CREATE UNIQUE INDEX users_email ON users (email) WHERE deleted_at IS NULL;
+    CREATE INDEX users_by_email ON users (email, id) WHERE deleted_at IS NULL;
+    CREATE INDEX users_by_created_at ON users (created_at, id) WHERE deleted_at IS NULL;",
+];
+
+/// A connection to a SQLite database, shared by the stores using it.
+#[derive(Clone, Debug)]
+pub struct Database(Arc<Mutex<Connection>>);
+
+impl Database {
+    /// Opens (or creates) the database at `path`, applying any pending
+    /// migrations.
+    ///
+    /// The locks of requests interrupted by a crash or restart are released,
+    /// so that they can be retried; the database must not be served by
+    /// another process at the same time.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, OpaqueError> {
+        let db = Self::connect(path)?;
+        let applied = db.migrate()?;
+        tracing::info!("{applied} Migrations Applied");
+        let released = cache::release_in_flight(&db.connection())?;
+        tracing::info!("{released} Interrupted Requests Released");
+        Ok(db)
+    }
+
+    /// Opens (or creates) the database at `path`, without applying any
+    /// migrations; see [Database::migrate].
+    pub fn connect(path: impl AsRef<Path>) -> Result<Self, OpaqueError> {
+        let path = path.as_ref();
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to Open Database {}", path.display()))?;
+        Ok(Self(Arc::new(Mutex::new(conn))))
+    }
+
+    /// Opens a private, in-memory database, applying all migrations.
+    pub fn in_memory() -> Result<Self, OpaqueError> {
+        let conn = Connection::open_in_memory().context("Failed to Open Database In Memory")?;
+        let db = Self(Arc::new(Mutex::new(conn)));
+        let applied = db.migrate()?;
+        tracing::info!("{applied} Migrations Applied");
+        Ok(db)
+    }
+
+    /// Applies the pending migrations, returning how many were applied.
+    pub fn migrate(&self) -> Result<usize, OpaqueError> {
+        let mut conn = self.connection();
+        let version: usize = conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .context("Failed to Read Schema Version")?;
+
+        let pending = MIGRATIONS.iter().enumerate().skip(version);
+        let mut applied = 0;
+        for (index, migration) in pending {
+            let tx = conn.transaction().context("Failed to Begin Migration")?;
+            tx.execute_batch(migration)
+                .with_context(|| format!("Failed to Apply Migration {}", index + 1))?;
+            tx.pragma_update(None, "user_version", index + 1)
+                .context("Failed to Update Schema Version")?;
+            tx.commit().context("Failed to Commit Migration")?;
+            applied += 1;
+        }
+        Ok(applied)
+    }
+
+    /// Whether `other` is a handle to the same connection.
+    pub(crate) fn is(&self, other: &Database) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Runs `work` with the connection on the blocking pool, so that waiting
+    /// for the database does not stall the runtime.
+    pub(crate) async fn run<T, E>(
+        &self,
+        work: impl FnOnce(&mut Connection) -> Result<T, E> + Send + 'static,
+    ) -> Result<T, E>
+    where
+        T: Send + 'static,
+        E: From<OpaqueError> + Send + 'static,
+    {
+        let db = self.clone();
+        obs::spawn_blocking(move || work(&mut db.connection()))
+            .await
+            .context("Database Task Failed")?
+    }
+
+    /// Locks the connection for the duration of a statement or transaction.
+    pub(crate) fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Milliseconds since the Unix epoch, as stored in the database.
+pub(crate) fn now_millis() -> i64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    now.as_millis() as i64
+}
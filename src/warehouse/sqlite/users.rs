@@ -0,0 +1,307 @@
+use super::cache;
+use super::now_millis;
+use super::Database;
+use super::SqliteCache;
+use crate::ikey::IKey;
+use crate::page::Cursor;
+use crate::page::Order;
+use crate::page::Page;
+use crate::page::UserQuery;
+use crate::page::UserSort;
+use crate::user::DeletedUser;
+use crate::user::NewUser;
+use crate::user::User;
+use crate::warehouse::CachedResponse;
+use crate::warehouse::EmailReuse;
+use crate::warehouse::Render;
+use crate::warehouse::UserRepoError;
+use crate::warehouse::UserStore;
+
+use color_eyre::eyre::Context;
+use rusqlite::params;
+use rusqlite::params_from_iter;
+use rusqlite::types::Value;
+use rusqlite::Connection;
+use rusqlite::OptionalExtension;
+
+/// A [UserStore] persisting [User]s in a SQLite [Database].
+///
+/// When [recording in](SqliteUserRepository::recording_in) a [SqliteCache]
+/// sharing its [Database], users are changed atomically with their
+/// idempotency records.
+#[derive(Clone, Debug)]
+pub struct SqliteUserRepository {
+    db: Database,
+    email_reuse: EmailReuse,
+    /// Whether the idempotency records are kept in `db`.
+    records: bool,
+}
+
+impl SqliteUserRepository {
+    pub fn new(db: Database) -> Self {
+        Self { db, email_reuse: EmailReuse::default(), records: false }
+    }
+
+    /// Records the responses of the requests changing users in the same
+    /// transaction, when the `cache` shares the [Database]; *otherwise* they
+    /// are left for the cache to record.
+    pub fn recording_in(self, cache: &SqliteCache) -> Self {
+        Self { records: cache.database().is(&self.db), ..self }
+    }
+
+    /// Sets when the emails of deleted users can be taken again.
+    pub fn with_email_reuse(self, email_reuse: EmailReuse) -> Self {
+        Self { email_reuse, ..self }
+    }
+
+    /// Fails when `email` is taken by a user other than `user_id`, or by a
+    /// tombstone unless emails are reused [on delete](EmailReuse::OnDelete).
+    fn email_is_available(
+        &self,
+        conn: &Connection,
+        email: &str,
+        user_id: Option<u64>,
+    ) -> Result<(), UserRepoError> {
+        let query = match self.email_reuse {
+            EmailReuse::OnDelete => {
+                "SELECT id FROM users WHERE email = ?1 AND id IS NOT ?2 AND deleted_at IS NULL"
+            }
+            EmailReuse::OnPurge => "SELECT id FROM users WHERE email = ?1 AND id IS NOT ?2",
+        };
+        let taken: Option<u64> = conn
+            .query_row(query, params![email, user_id], |row| row.get(0))
+            .optional()
+            .context("Failed to Select User by Email")?;
+        match taken {
+            Some(_) => Err(UserRepoError::EmailTaken(email.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    fn insert(&self, conn: &Connection, new_user: &NewUser) -> Result<User, UserRepoError> {
+        self.email_is_available(conn, &new_user.email, None)?;
+        tracing::info!("Email Is Free");
+
+        conn.execute(
+            "INSERT INTO users (email, created_at) VALUES (?1, ?2)",
+            params![new_user.email, now_millis()],
+        )
+        .context("Failed to Insert User")?;
+        let new_id = conn.last_insert_rowid() as u64;
+        tracing::info!("New User Inserted to DB");
+        Ok(User::new(new_id, new_user.email.to_owned()))
+    }
+
+    fn replace(&self, conn: &Connection, user: &User) -> Result<User, UserRepoError> {
+        self.email_is_available(conn, &user.email, Some(user.id))?;
+        tracing::info!("Email Is Free");
+
+        let updated = conn
+            .execute(
+                "UPDATE users SET email = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+                params![user.email, user.id],
+            )
+            .context("Failed to Update User")?;
+        if updated == 0 {
+            return Err(UserRepoError::UserNotFound(user.id));
+        }
+        tracing::info!("User Updated in DB");
+        Ok(user.clone())
+    }
+
+    fn tombstone(conn: &Connection, user_id: u64) -> Result<DeletedUser, UserRepoError> {
+        let user = Self::find(conn, user_id)?;
+        let deleted_at = now_millis();
+        conn.execute(
+            "UPDATE users SET deleted_at = ?1 WHERE id = ?2",
+            params![deleted_at, user_id],
+        )
+        .context("Failed to Delete User")?;
+        tracing::info!("User Deleted in DB");
+        Ok(DeletedUser { user, deleted_at })
+    }
+
+    fn remove(conn: &Connection, user_id: u64) -> Result<(), UserRepoError> {
+        let removed = conn
+            .execute("DELETE FROM users WHERE id = ?1", params![user_id])
+            .context("Failed to Purge User")?;
+        if removed == 0 {
+            return Err(UserRepoError::UserNotFound(user_id));
+        }
+        tracing::info!("User Purged from DB");
+        Ok(())
+    }
+
+    /// The sort key and id of a `cursor`, as compared to the sort column and
+    /// id of a row.
+    fn position(cursor: &Cursor) -> [Value; 2] {
+        match cursor {
+            Cursor::Id(id) => [Value::Integer(*id as i64), Value::Integer(*id as i64)],
+            Cursor::Email(email, id) => [Value::Text(email.clone()), Value::Integer(*id as i64)],
+            Cursor::CreatedAt(created_at, id) => {
+                [Value::Integer(*created_at), Value::Integer(*id as i64)]
+            }
+        }
+    }
+
+    /// The [User] with id `user_id`, unless it was deleted.
+    fn find(conn: &Connection, user_id: u64) -> Result<User, UserRepoError> {
+        conn.query_row(
+            "SELECT id, email FROM users WHERE id = ?1 AND deleted_at IS NULL",
+            params![user_id],
+            |row| Ok(User::new(row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .context("Failed to Select User")?
+        .ok_or(UserRepoError::UserNotFound(user_id))
+    }
+}
+
+impl UserStore for SqliteUserRepository {
+    fn create(&mut self, new_user: &NewUser) -> Result<User, UserRepoError> {
+        let conn = self.db.connection();
+        self.insert(&conn, new_user)
+    }
+
+    fn create_recorded(
+        &mut self,
+        new_user: &NewUser,
+        key: &IKey,
+        render: &Render<User>,
+    ) -> Result<User, UserRepoError> {
+        if !self.records {
+            return self.create(new_user);
+        }
+        let mut conn = self.db.connection();
+        let tx = conn.transaction().context("Failed to Begin Transaction")?;
+        let user = self.insert(&tx, new_user)?;
+        cache::record(&tx, key, &render(&user))?;
+        tx.commit().context("Failed to Commit User and Idempotency Record")?;
+        tracing::info!("User Recorded Under {key}");
+        Ok(user)
+    }
+
+    fn update(&mut self, user: &User) -> Result<User, UserRepoError> {
+        let conn = self.db.connection();
+        self.replace(&conn, user)
+    }
+
+    fn update_recorded(
+        &mut self,
+        user: &User,
+        key: &IKey,
+        render: &Render<User>,
+    ) -> Result<User, UserRepoError> {
+        if !self.records {
+            return self.update(user);
+        }
+        let mut conn = self.db.connection();
+        let tx = conn.transaction().context("Failed to Begin Transaction")?;
+        let user = self.replace(&tx, user)?;
+        cache::record(&tx, key, &render(&user))?;
+        tx.commit().context("Failed to Commit User and Idempotency Record")?;
+        tracing::info!("User Recorded Under {key}");
+        Ok(user)
+    }
+
+    fn delete(&mut self, user_id: u64) -> Result<DeletedUser, UserRepoError> {
+        let conn = self.db.connection();
+        Self::tombstone(&conn, user_id)
+    }
+
+    fn delete_recorded(
+        &mut self,
+        user_id: u64,
+        key: &IKey,
+        render: &Render<DeletedUser>,
+    ) -> Result<DeletedUser, UserRepoError> {
+        if !self.records {
+            return self.delete(user_id);
+        }
+        let mut conn = self.db.connection();
+        let tx = conn.transaction().context("Failed to Begin Transaction")?;
+        let deleted = Self::tombstone(&tx, user_id)?;
+        cache::record(&tx, key, &render(&deleted))?;
+        tx.commit().context("Failed to Commit Tombstone and Idempotency Record")?;
+        tracing::info!("Tombstone Recorded Under {key}");
+        Ok(deleted)
+    }
+
+    fn purge(&mut self, user_id: u64) -> Result<(), UserRepoError> {
+        let conn = self.db.connection();
+        Self::remove(&conn, user_id)
+    }
+
+    fn purge_recorded(
+        &mut self,
+        user_id: u64,
+        key: &IKey,
+        response: &CachedResponse,
+    ) -> Result<(), UserRepoError> {
+        if !self.records {
+            return self.purge(user_id);
+        }
+        let mut conn = self.db.connection();
+        let tx = conn.transaction().context("Failed to Begin Transaction")?;
+        Self::remove(&tx, user_id)?;
+        cache::record(&tx, key, response)?;
+        tx.commit().context("Failed to Commit Purge and Idempotency Record")?;
+        tracing::info!("Purge Recorded Under {key}");
+        Ok(())
+    }
+
+    /// Seeks past the cursor of the `query` in the index of its sort.
+    fn list(&self, query: &UserQuery) -> Result<Page, UserRepoError> {
+        let column = match query.sort {
+            UserSort::Id => "id",
+            UserSort::Email => "email",
+            UserSort::CreatedAt => "created_at",
+        };
+        let (direction, comparison) = match query.order {
+            Order::Asc => ("ASC", ">"),
+            Order::Desc => ("DESC", "<"),
+        };
+        // An empty prefix or part matches every email.
+        let mut values = vec![
+            Value::Text(query.email_prefix.clone().unwrap_or_default()),
+            Value::Text(query.email_contains.clone().unwrap_or_default()),
+        ];
+        let after = match &query.after {
+            Some(after) => {
+                values.extend(Self::position(after));
+                format!("AND ({column}, id) {comparison} (?3, ?4)")
+            }
+            None => String::new(),
+        };
+        let sql = format!(
+            "SELECT id, email, created_at FROM users
+            WHERE deleted_at IS NULL
+            AND substr(email, 1, length(?1)) = ?1 AND instr(email, ?2) > 0 {after}
+            ORDER BY {column} {direction}, id {direction} LIMIT {}",
+            query.limit + 1
+        );
+
+        let conn = self.db.connection();
+        let mut statement = conn.prepare(&sql).context("Failed to Prepare Users")?;
+        let rows = statement
+            .query_map(params_from_iter(values), |row| {
+                let user = User::new(row.get(0)?, row.get(1)?);
+                Ok((Cursor::of(query.sort, &user, row.get(2)?), user))
+            })
+            .context("Failed to Select Users")?
+            .collect::<Result<_, _>>()
+            .context("Failed to Read Users")?;
+        Ok(Page::new(rows, query.limit))
+    }
+
+    fn ping(&self) -> Result<(), UserRepoError> {
+        let conn = self.db.connection();
+        conn.query_row("SELECT 1", [], |_| Ok(())).context("Failed to Reach Database")?;
+        Ok(())
+    }
+
+    fn get(&self, user_id: u64) -> Result<User, UserRepoError> {
+        let conn = self.db.connection();
+        Self::find(&conn, user_id)
+    }
+}
@@ -0,0 +1,197 @@
+use crate::fingerprint::Fingerprint;
+use crate::ikey::IKey;
+use crate::page::Page;
+use crate::page::UserQuery;
+use crate::user::DeletedUser;
+use crate::user::NewUser;
+use crate::user::User;
+use crate::warehouse::CacheError;
+use crate::warehouse::CachedResponse;
+use crate::warehouse::Lock;
+use crate::warehouse::UserRepoError;
+
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt::Debug;
+
+/// A type-erased [IdempotencyStore], for choosing the backend at runtime.
+pub type DynStore = Box<dyn IdempotencyStore>;
+
+/// Renders the response recorded for a [User], or its tombstone, by a
+/// [UserStore].
+pub type Render<T> = dyn Fn(&T) -> CachedResponse + Send + Sync;
+
+/// Storage for the responses of requests made with an [IKey].
+///
+/// A store is owned by a single
+/// [CacheManager](crate::middleware::cache::manager::CacheManager), which
+/// serializes the access to it.
+#[axum::async_trait]
+pub trait IdempotencyStore: Debug + Send + 'static {
+    /// Given an [IKey], either returns a [CachedResponse] on a cache hit, or
+    /// [CacheError] on miss.
+    async fn get(&mut self, key: &IKey) -> Result<CachedResponse, CacheError>;
+
+    /// Given a [IKey] and [CachedResponse], performs an upsert, completing any
+    /// [Lock] previously acquired for `key`.
+    async fn set(&mut self, key: &IKey, res: &CachedResponse) -> Result<(), CacheError>;
+
+    /// Given an [IKey], marks it as being processed unless a request with the
+    /// same key was seen already; see [Lock].
+    async fn lock(&mut self, key: &IKey, fingerprint: &Fingerprint) -> Result<Lock, CacheError>;
+
+    /// Removes `key` from the store, releasing any [Lock] held on it.
+    async fn delete(&mut self, key: &IKey) -> Result<(), CacheError>;
+
+    /// Removes all expired entries, returning how many were removed.
+    async fn expire(&mut self) -> Result<usize, CacheError>;
+
+    /// The number of entries in the store, including expired ones.
+    async fn size(&mut self) -> Result<usize, CacheError>;
+
+    /// Releases the [Lock]s still held once no request is processed anymore,
+    /// so that the interrupted requests can be retried; returns how many were
+    /// released. The locks of an in-memory store are dropped with it.
+    async fn release(&mut self) -> Result<usize, CacheError> {
+        Ok(0)
+    }
+
+    /// The number of entries evicted to make room for new ones.
+    fn evictions(&self) -> u64 {
+        0
+    }
+}
+
+#[axum::async_trait]
+impl IdempotencyStore for DynStore {
+    async fn get(&mut self, key: &IKey) -> Result<CachedResponse, CacheError> {
+        (**self).get(key).await
+    }
+
+    async fn set(&mut self, key: &IKey, res: &CachedResponse) -> Result<(), CacheError> {
+        (**self).set(key, res).await
+    }
+
+    async fn lock(&mut self, key: &IKey, fingerprint: &Fingerprint) -> Result<Lock, CacheError> {
+        (**self).lock(key, fingerprint).await
+    }
+
+    async fn delete(&mut self, key: &IKey) -> Result<(), CacheError> {
+        (**self).delete(key).await
+    }
+
+    async fn expire(&mut self) -> Result<usize, CacheError> {
+        (**self).expire().await
+    }
+
+    async fn size(&mut self) -> Result<usize, CacheError> {
+        (**self).size().await
+    }
+
+    async fn release(&mut self) -> Result<usize, CacheError> {
+        (**self).release().await
+    }
+
+    fn evictions(&self) -> u64 {
+        (**self).evictions()
+    }
+}
+
+/// When the email of a deleted [User] can be taken by another.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailReuse {
+    /// As soon as the user is deleted, even softly.
+    #[default]
+    OnDelete,
+    /// Only once the user is purged; a tombstone keeps its email.
+    OnPurge,
+}
+
+/// Storage for [User]s, used by the [Service](crate::service::Service).
+///
+/// Deleted users are kept as tombstones until they are purged, and are
+/// neither found nor listed.
+pub trait UserStore: Debug + Send + Sync + 'static {
+    /// Create a new [User] from [NewUser], unless the email is taken.
+    fn create(&mut self, new_user: &NewUser) -> Result<User, UserRepoError>;
+
+    /// Create a new [User] like [UserStore::create], and record the response
+    /// `render`ed for it under `key` in the same transaction.
+    ///
+    /// Only a store sharing its database with the [IdempotencyStore] can do
+    /// that atomically; by default the user is created, and the response is
+    /// left for the
+    /// [IdempotencyLayer](crate::middleware::cache::IdempotencyLayer)
+    /// to record.
+    fn create_recorded(
+        &mut self,
+        new_user: &NewUser,
+        key: &IKey,
+        render: &Render<User>,
+    ) -> Result<User, UserRepoError> {
+        let _ = (key, render);
+        self.create(new_user)
+    }
+
+    /// Replaces the [User] with the id of `user`, unless its email is taken
+    /// by another user; *otherwise* `NotFound`.
+    fn update(&mut self, user: &User) -> Result<User, UserRepoError>;
+
+    /// Replaces a [User] like [UserStore::update], and record the response
+    /// `render`ed for it under `key` in the same transaction; see
+    /// [UserStore::create_recorded].
+    fn update_recorded(
+        &mut self,
+        user: &User,
+        key: &IKey,
+        render: &Render<User>,
+    ) -> Result<User, UserRepoError> {
+        let _ = (key, render);
+        self.update(user)
+    }
+
+    /// Deletes the [User] with `user_id` softly, keeping its tombstone;
+    /// *otherwise* `NotFound`.
+    fn delete(&mut self, user_id: u64) -> Result<DeletedUser, UserRepoError>;
+
+    /// Deletes a [User] like [UserStore::delete], and record the response
+    /// `render`ed for its tombstone under `key` in the same transaction; see
+    /// [UserStore::create_recorded].
+    fn delete_recorded(
+        &mut self,
+        user_id: u64,
+        key: &IKey,
+        render: &Render<DeletedUser>,
+    ) -> Result<DeletedUser, UserRepoError> {
+        let _ = (key, render);
+        self.delete(user_id)
+    }
+
+    /// Removes the [User] with `user_id`, or its tombstone, for good;
+    /// *otherwise* `NotFound`.
+    fn purge(&mut self, user_id: u64) -> Result<(), UserRepoError>;
+
+    /// Purges a [User] like [UserStore::purge], and record the `response`
+    /// under `key` in the same transaction; see [UserStore::create_recorded].
+    fn purge_recorded(
+        &mut self,
+        user_id: u64,
+        key: &IKey,
+        response: &CachedResponse,
+    ) -> Result<(), UserRepoError> {
+        let _ = (key, response);
+        self.purge(user_id)
+    }
+
+    /// The page of users matching the `query`, in its order.
+    fn list(&self, query: &UserQuery) -> Result<Page, UserRepoError>;
+
+    /// Returns [User] with id `user_id`; *otherwise* `NotFound`.
+    fn get(&self, user_id: u64) -> Result<User, UserRepoError>;
+
+    /// Checks that the store is reachable.
+    fn ping(&self) -> Result<(), UserRepoError> {
+        Ok(())
+    }
+}
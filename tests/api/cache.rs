@@ -0,0 +1,193 @@
+use lib::fingerprint::Fingerprint;
+use lib::ikey::IKey;
+use lib::user::User;
+use lib::warehouse::Cache;
+use lib::warehouse::CacheConfig;
+use lib::warehouse::CacheError;
+use lib::warehouse::CachedResponse;
+use lib::warehouse::IdempotencyStore;
+use lib::warehouse::Lock;
+
+use hyper::HeaderMap;
+use hyper::Method;
+use hyper::StatusCode;
+use std::time::Duration;
+
+pub fn ikey() -> IKey {
+    IKey::try_from("key".to_string()).unwrap()
+}
+
+pub fn fingerprint(body: &str) -> Fingerprint {
+    Fingerprint::new(&Method::POST, "/users", body.as_bytes())
+}
+
+fn short_lived_cache() -> Cache {
+    let ttl = Duration::from_secs(60);
+    Cache::with_config(&CacheConfig { ttl, ..CacheConfig::default() })
+}
+
+fn small_cache() -> Cache {
+    Cache::with_config(&CacheConfig { capacity: 2, ..CacheConfig::default() })
+}
+
+fn key(key: &str) -> IKey {
+    IKey::try_from(key.to_string()).unwrap()
+}
+
+pub fn cached_response() -> CachedResponse {
+    let user = User::new(1, "first@email".to_string());
+    let body = serde_json::to_vec(&user).unwrap().into();
+    let headers = HeaderMap::new();
+    CachedResponse { status: StatusCode::OK, headers, body, fingerprint: fingerprint("first") }
+}
+
+#[tokio::test]
+async fn lock_on_new_key_is_acquired() {
+    // I. Arrange
+    let mut cache = Cache::new();
+
+    // II. Act
+    let lock = cache.lock(&ikey(), &fingerprint("first")).await.unwrap();
+
+    // III. Assert
+    assert!(matches!(lock, Lock::Acquired));
+    assert!(cache.get(&ikey()).await.is_err());
+}
+
+#[tokio::test]
+async fn lock_on_started_key_is_in_flight() {
+    // I. Arrange
+    let mut cache = Cache::new();
+    cache.lock(&ikey(), &fingerprint("first")).await.unwrap();
+
+    // II. Act
+    let lock = cache.lock(&ikey(), &fingerprint("first")).await.unwrap();
+
+    // III. Assert
+    assert!(matches!(lock, Lock::InFlight));
+}
+
+#[tokio::test]
+async fn lock_on_completed_key_returns_response() {
+    // I. Arrange
+    let mut cache = Cache::new();
+    cache.lock(&ikey(), &fingerprint("first")).await.unwrap();
+    cache.set(&ikey(), &cached_response()).await.unwrap();
+
+    // II. Act
+    let lock = cache.lock(&ikey(), &fingerprint("first")).await.unwrap();
+
+    // III. Assert
+    let Lock::Completed(cached) = lock else { panic!("Expected Completed, got {lock}") };
+    assert_eq!(cached_response().body, cached.body);
+}
+
+#[tokio::test]
+async fn delete_releases_lock() {
+    // I. Arrange
+    let mut cache = Cache::new();
+    cache.lock(&ikey(), &fingerprint("first")).await.unwrap();
+
+    // II. Act
+    cache.delete(&ikey()).await.unwrap();
+    let lock = cache.lock(&ikey(), &fingerprint("first")).await.unwrap();
+
+    // III. Assert
+    assert!(matches!(lock, Lock::Acquired));
+}
+
+#[tokio::test]
+async fn lock_with_different_fingerprint_is_mismatch() {
+    // I. Arrange
+    let mut cache = Cache::new();
+    cache.lock(&ikey(), &fingerprint("first")).await.unwrap();
+    cache.set(&ikey(), &cached_response()).await.unwrap();
+
+    // II. Act
+    let lock = cache.lock(&ikey(), &fingerprint("second")).await.unwrap();
+
+    // III. Assert
+    assert!(matches!(lock, Lock::Mismatch));
+}
+
+#[test]
+fn fingerprint_ignores_json_key_order_and_whitespace() {
+    let original = fingerprint(r#"{"email":"first@email","id":1}"#);
+    let reordered = fingerprint(r#"{ "id": 1, "email": "first@email" }"#);
+    let different = fingerprint(r#"{"email":"second@email","id":1}"#);
+
+    assert_eq!(original, reordered);
+    assert_ne!(original, different);
+}
+
+#[tokio::test(start_paused = true)]
+async fn expired_entry_is_a_miss() {
+    // I. Arrange
+    let mut cache = short_lived_cache();
+    cache.lock(&ikey(), &fingerprint("first")).await.unwrap();
+    cache.set(&ikey(), &cached_response()).await.unwrap();
+    assert!(cache.get(&ikey()).await.is_ok());
+
+    // II. Act
+    tokio::time::advance(Duration::from_secs(60)).await;
+    let lock = cache.lock(&ikey(), &fingerprint("second")).await.unwrap();
+
+    // III. Assert
+    assert!(matches!(lock, Lock::Acquired));
+}
+
+#[tokio::test(start_paused = true)]
+async fn expire_removes_only_expired_entries() {
+    // I. Arrange
+    let mut cache = short_lived_cache();
+    let fresh = IKey::try_from("fresh".to_string()).unwrap();
+    cache.lock(&ikey(), &fingerprint("first")).await.unwrap();
+    tokio::time::advance(Duration::from_secs(30)).await;
+    cache.lock(&fresh, &fingerprint("first")).await.unwrap();
+    tokio::time::advance(Duration::from_secs(30)).await;
+
+    // II. Act
+    let expired = cache.expire().await.unwrap();
+
+    // III. Assert
+    assert_eq!(1, expired);
+    assert_eq!(1, cache.len());
+    let lock = cache.lock(&fresh, &fingerprint("first")).await.unwrap();
+    assert!(matches!(lock, Lock::InFlight));
+}
+
+#[tokio::test]
+async fn full_cache_evicts_least_recently_used() {
+    // I. Arrange
+    let mut cache = small_cache();
+    cache.set(&key("a"), &cached_response()).await.unwrap();
+    cache.set(&key("b"), &cached_response()).await.unwrap();
+    cache.get(&key("a")).await.unwrap();
+
+    // II. Act
+    cache.set(&key("c"), &cached_response()).await.unwrap();
+
+    // III. Assert
+    assert_eq!(2, cache.len());
+    assert_eq!(1, cache.evictions());
+    assert!(cache.get(&key("a")).await.is_ok());
+    assert!(cache.get(&key("b")).await.is_err());
+    assert!(cache.get(&key("c")).await.is_ok());
+}
+
+#[tokio::test]
+async fn full_cache_does_not_evict_requests_in_flight() {
+    // I. Arrange
+    let mut cache = small_cache();
+    cache.lock(&key("a"), &fingerprint("first")).await.unwrap();
+    cache.lock(&key("b"), &fingerprint("first")).await.unwrap();
+
+    // II. Act
+    let lock = cache.lock(&key("c"), &fingerprint("first")).await;
+
+    // III. Assert
+    assert!(matches!(lock, Err(CacheError::Full(2))));
+    assert_eq!(0, cache.evictions());
+    let lock = cache.lock(&key("a"), &fingerprint("first")).await.unwrap();
+    assert!(matches!(lock, Lock::InFlight));
+}
@@ -0,0 +1,75 @@
+use lib::cli::Cli;
+use lib::cli::Command;
+use lib::obs::LogFormat;
+use lib::warehouse::Database;
+
+use clap::Parser;
+use std::fs;
+use tempfile::TempDir;
+
+#[test]
+fn serve_arguments_override_config() {
+    // I. Arrange
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("icapi.toml");
+    fs::write(&path, "[server]\nbind = \"127.0.0.1:3000\"\n[cache]\ncapacity = 5\n").unwrap();
+    let path = path.to_str().unwrap();
+    let args = ["server", "serve", "-c", path, "--bind", "0.0.0.0:9000", "--log-format", "json"];
+
+    // II. Act
+    let Some(Command::Serve(serve)) = Cli::try_parse_from(args).unwrap().command else {
+        panic!("Expected the Serve Command");
+    };
+    let config = serve.load().unwrap();
+
+    // III. Assert
+    assert_eq!("0.0.0.0:9000", config.server.bind.to_string());
+    assert_eq!(LogFormat::Json, config.log.format);
+    assert_eq!(5, config.cache.capacity);
+}
+
+#[test]
+fn unknown_command_is_rejected() {
+    // I. Arrange
+    let args = ["server", "deploy"];
+
+    // II. Act
+    let res = Cli::try_parse_from(args);
+
+    // III. Assert
+    assert!(res.is_err());
+}
+
+#[tokio::test]
+async fn check_config_rejects_invalid_config() {
+    // I. Arrange
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("icapi.toml");
+    fs::write(&path, "[storage]\nbackend = \"sqlite\"\n").unwrap();
+    let cli = Cli::try_parse_from(["server", "check-config", "-c", path.to_str().unwrap()]);
+
+    // II. Act
+    let res = cli.unwrap().run().await;
+
+    // III. Assert
+    let error = res.unwrap_err().to_string();
+    assert_eq!("Invalid Config: storage.path is required by the sqlite backend", error);
+}
+
+#[tokio::test]
+async fn migrate_applies_pending_migrations() {
+    // I. Arrange
+    let dir = TempDir::new().unwrap();
+    let db = dir.path().join("icapi.db");
+    let path = dir.path().join("icapi.toml");
+    let toml = format!("[storage]\nbackend = \"sqlite\"\npath = {:?}\n", db.to_str().unwrap());
+    fs::write(&path, toml).unwrap();
+    let cli = Cli::try_parse_from(["server", "migrate", "-c", path.to_str().unwrap()]);
+
+    // II. Act
+    cli.unwrap().run().await.unwrap();
+
+    // III. Assert
+    let pending = Database::connect(&db).unwrap().migrate().unwrap();
+    assert_eq!(0, pending);
+}
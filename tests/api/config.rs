@@ -0,0 +1,154 @@
+use lib::config::Config;
+use lib::config::StorageBackend;
+use lib::middleware::cache::CacheBackend;
+use lib::middleware::cache::FailurePolicy;
+use lib::obs::LogFormat;
+use lib::obs::LogRotation;
+use lib::warehouse::EmailReuse;
+
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+use tempfile::NamedTempFile;
+
+fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+    vars.iter().map(|(var, val)| (var.to_string(), val.to_string())).collect()
+}
+
+#[test]
+fn defaults_are_valid() {
+    // I. Arrange
+    let (toml, env) = ("", env(&[]));
+
+    // II. Act
+    let config = Config::from_sources(toml, env).unwrap();
+
+    // III. Assert
+    assert_eq!(8080, config.server.bind.port());
+    assert_eq!(StorageBackend::Memory, config.storage.backend);
+    assert_eq!(LogFormat::Pretty, config.log.format);
+}
+
+#[test]
+fn file_settings_are_applied() {
+    // I. Arrange
+    let toml = r#"
+        [server]
+        bind = "0.0.0.0:3000"
+        body_limit = 1024
+
+        [cache]
+        backend = "direct"
+        ttl_secs = 60
+        capacity = 100
+        on_failure = "open"
+
+        [storage]
+        backend = "sqlite"
+        path = "icapi.db"
+        email_reuse = "on_purge"
+
+        [log]
+        format = "json"
+        dir = "logs"
+        rotation = "hourly"
+    "#;
+
+    // II. Act
+    let config = Config::from_sources(toml, env(&[])).unwrap();
+    let idempotency = config.idempotency();
+
+    // III. Assert
+    assert_eq!("0.0.0.0:3000".parse::<SocketAddr>().unwrap(), config.server.bind);
+    assert_eq!(StorageBackend::Sqlite, config.storage.backend);
+    assert_eq!(EmailReuse::OnPurge, config.storage.email_reuse);
+    assert_eq!(LogFormat::Json, config.log.format);
+    assert_eq!(Some(Path::new("logs")), config.log.dir.as_deref());
+    assert_eq!(LogRotation::Hourly, config.log.rotation);
+    assert_eq!(CacheBackend::Direct, idempotency.backend);
+    assert_eq!(FailurePolicy::Open, idempotency.on_failure);
+    assert_eq!(Duration::from_secs(60), idempotency.cache.ttl);
+    assert_eq!(100, idempotency.cache.capacity);
+    assert_eq!(1024, idempotency.body_limit);
+}
+
+#[test]
+fn environment_overrides_file() {
+    // I. Arrange
+    let toml = "[server]\nbind = \"0.0.0.0:3000\"\n\n[cache]\ncapacity = 100\n";
+    let env = env(&[
+        ("ICAPI_SERVER_BIND", "127.0.0.1:4000"),
+        ("ICAPI_CACHE_CAPACITY", "5"),
+        ("ICAPI_LOG_FORMAT", "json"),
+        ("ICAPI_TRACE_ENDPOINT", "http://localhost:4318/v1/traces"),
+        ("UNRELATED_CACHE_CAPACITY", "0"),
+    ]);
+
+    // II. Act
+    let config = Config::from_sources(toml, env).unwrap();
+
+    // III. Assert
+    assert_eq!(4000, config.server.bind.port());
+    assert_eq!(5, config.cache.capacity);
+    assert_eq!(LogFormat::Json, config.log.format);
+    assert_eq!(Some("http://localhost:4318/v1/traces"), config.trace.endpoint.as_deref());
+}
+
+#[test]
+fn environment_strings_are_not_parsed() {
+    // I. Arrange
+    let env = env(&[
+        ("ICAPI_STORAGE_BACKEND", "sqlite"),
+        ("ICAPI_STORAGE_PATH", "2024"),
+        ("ICAPI_TRACE_SERVICE_NAME", "123"),
+        ("ICAPI_LOG_FILTER", "true"),
+    ]);
+
+    // II. Act
+    let config = Config::from_sources("", env).unwrap();
+
+    // III. Assert
+    assert_eq!(Some(Path::new("2024")), config.storage.path.as_deref());
+    assert_eq!("123", config.trace.service_name);
+    assert_eq!("true", config.log.filter);
+}
+
+#[test]
+fn config_is_loaded_from_file() {
+    // I. Arrange
+    let mut file = NamedTempFile::new().unwrap();
+    writeln!(file, "[cache]\nttl_secs = 30").unwrap();
+
+    // II. Act
+    let config = Config::load(Some(file.path())).unwrap();
+
+    // III. Assert
+    assert_eq!(30, config.cache.ttl_secs);
+}
+
+#[test]
+fn invalid_settings_are_rejected() {
+    // I. Arrange
+    let invalid = [
+        ("[server]\nbind = \"127.0.0.1:0\"", vec![]),
+        ("[cache]\ncapacity = 0", vec![]),
+        ("[cache]\nbackend = \"remote\"", vec![]),
+        ("[storage]\nbackend = \"sqlite\"", vec![]),
+        ("[server]\nport = 8080", vec![]),
+        ("", env(&[("ICAPI_CACHE_SHARDS", "0")])),
+        ("", env(&[("ICAPI_CACHE_TTL_SECS", "a day")])),
+        ("", env(&[("ICAPI_VERBOSE", "true")])),
+        ("", env(&[("ICAPI_TRACE_ENDPOINT", "collector:4318")])),
+        ("", env(&[("RUST_LOG", "icapi=loud")])),
+    ];
+
+    // II. Act
+    let errors: Vec<_> = invalid
+        .into_iter()
+        .map(|(toml, env)| Config::from_sources(toml, env).unwrap_err().to_string())
+        .collect();
+
+    // III. Assert
+    insta::assert_yaml_snapshot!(errors);
+}
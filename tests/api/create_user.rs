@@ -1,4 +1,5 @@
 use crate::test_app::TestApp;
+use lib::user::NewUser;
 use lib::warehouse::UserRepository;
 
 use hyper::body::to_bytes as BodyToBytes;
@@ -61,7 +62,7 @@ async fn duplicate_request_without_key_is_409() {
 }
 
 #[tokio::test]
-async fn duplicate_request_with_key_is_201() {
+async fn duplicate_request_with_key_is_replayed() {
     // I. Arrange
     let app = TestApp::new(UserRepository::new()).await;
     let client = hyper::Client::new();
@@ -77,11 +78,14 @@ async fn duplicate_request_with_key_is_201() {
 
     // III. Assert
     assert_eq!(StatusCode::OK, original.status());
-    assert_eq!(StatusCode::CREATED, duplicate.status());
+    assert_eq!(StatusCode::OK, duplicate.status());
 
     assert_eq!("application/json", original.headers().get("Content-Type").unwrap());
     assert_eq!("application/json", duplicate.headers().get("Content-Type").unwrap());
 
+    assert!(original.headers().get("Idempotent-Replayed").is_none());
+    assert_eq!("true", duplicate.headers().get("Idempotent-Replayed").unwrap());
+
     let original = BodyToBytes(original.into_body()).await.unwrap();
     let original: Value = serde_json::from_slice(&original).unwrap();
     insta::assert_json_snapshot!(&original);
@@ -92,3 +96,98 @@ async fn duplicate_request_with_key_is_201() {
 
     assert_eq!(original, duplicate);
 }
+
+#[tokio::test]
+async fn concurrent_requests_with_key_create_one_user() {
+    // I. Arrange
+    let app = TestApp::new(UserRepository::new()).await;
+    let client = hyper::Client::new();
+    let requests: Vec<_> =
+        (0..5).map(|_| TestApp::with_idempotency(app.post_user(&app.test_user), 1)).collect();
+    let get_users = app.get_users();
+    spawn(async move {
+        app.run().await.unwrap();
+    });
+
+    // II. Act
+    let responses: Vec<_> = requests
+        .into_iter()
+        .map(|req| {
+            let client = client.clone();
+            spawn(async move { client.request(req).await.unwrap() })
+        })
+        .collect();
+    let mut originals = 0;
+    for response in responses {
+        let response = response.await.unwrap();
+        let replayed = response.headers().contains_key("Idempotent-Replayed");
+        match response.status() {
+            StatusCode::OK if !replayed => originals += 1,
+            StatusCode::OK | StatusCode::CONFLICT => {}
+            otherwise => panic!("Unexpected status {otherwise}"),
+        }
+    }
+
+    // III. Assert
+    assert_eq!(1, originals);
+
+    let users = client.request(get_users).await.unwrap();
+    let users = BodyToBytes(users.into_body()).await.unwrap();
+    let users: Vec<Value> = serde_json::from_slice(&users).unwrap();
+    assert_eq!(1, users.len());
+}
+
+#[tokio::test]
+async fn request_reusing_key_with_different_payload_is_422() {
+    // I. Arrange
+    let app = TestApp::new(UserRepository::new()).await;
+    let client = hyper::Client::new();
+    let original = TestApp::with_idempotency(app.post_user(&app.test_user), 1);
+    let other_user = NewUser::new("second@email".to_string());
+    let reuse = TestApp::with_idempotency(app.post_user(&other_user), 1);
+    spawn(async move {
+        app.run().await.unwrap();
+    });
+
+    // II. Act
+    let original = client.request(original).await.unwrap();
+    let reuse = client.request(reuse).await.unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::OK, original.status());
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, reuse.status());
+    assert_eq!("application/json", reuse.headers().get("Content-Type").unwrap());
+
+    let reuse = BodyToBytes(reuse.into_body()).await.unwrap();
+    let reuse: Value = serde_json::from_slice(&reuse).unwrap();
+    insta::assert_json_snapshot!(&reuse);
+}
+
+#[tokio::test]
+async fn duplicate_request_with_key_replays_error() {
+    // I. Arrange
+    let app = TestApp::new(UserRepository::new()).await;
+    let client = hyper::Client::new();
+    let first = TestApp::with_idempotency(app.post_user(&app.test_user), 1);
+    let original = TestApp::with_idempotency(app.post_user(&app.test_user), 2);
+    let duplicate = TestApp::with_idempotency(app.post_user(&app.test_user), 2);
+    spawn(async move {
+        app.run().await.unwrap();
+    });
+
+    // II. Act
+    let first = client.request(first).await.unwrap();
+    let original = client.request(original).await.unwrap();
+    let duplicate = client.request(duplicate).await.unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::OK, first.status());
+    assert_eq!(StatusCode::CONFLICT, original.status());
+    assert_eq!(StatusCode::CONFLICT, duplicate.status());
+    assert_eq!("application/json", duplicate.headers().get("Content-Type").unwrap());
+    assert_eq!("true", duplicate.headers().get("Idempotent-Replayed").unwrap());
+
+    let original = BodyToBytes(original.into_body()).await.unwrap();
+    let duplicate = BodyToBytes(duplicate.into_body()).await.unwrap();
+    assert_eq!(original, duplicate);
+}
@@ -0,0 +1,174 @@
+use crate::test_app::TestApp;
+use lib::server::UserApi;
+use lib::user::NewUser;
+use lib::warehouse::EmailReuse;
+
+use axum::response::Response;
+use hyper::body::to_bytes as BodyToBytes;
+use hyper::StatusCode;
+use serde_json::Value;
+use tokio::spawn;
+use tower::ServiceExt;
+
+async fn body(response: Response) -> Value {
+    let body = BodyToBytes(response.into_body()).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn deleted_user_is_hidden() {
+    // I. Arrange
+    let app = TestApp::new(TestApp::init_repo_data()).await;
+
+    // II. Act
+    let response = app.router().oneshot(app.delete_user(1)).await.unwrap();
+    let found = app.router().oneshot(app.get_user(1)).await.unwrap();
+    let listed = app.router().oneshot(app.get_users()).await.unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::OK, response.status());
+    let tombstone = body(response).await;
+    assert_eq!(1, tombstone["id"]);
+    assert_eq!("first@email", tombstone["email"]);
+    assert!(tombstone["deleted_at"].as_i64().unwrap() > 0);
+    assert_eq!(StatusCode::NOT_FOUND, found.status());
+    let listed = body(listed).await;
+    assert!(listed.as_array().unwrap().iter().all(|user| user["id"] != 1));
+}
+
+#[tokio::test]
+async fn purged_user_is_204() {
+    // I. Arrange
+    let app = TestApp::new(TestApp::init_repo_data()).await;
+
+    // II. Act
+    let response = app.router().oneshot(app.purge_user(2)).await.unwrap();
+    let found = app.router().oneshot(app.get_user(2)).await.unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::NO_CONTENT, response.status());
+    assert!(BodyToBytes(response.into_body()).await.unwrap().is_empty());
+    assert_eq!(StatusCode::NOT_FOUND, found.status());
+}
+
+#[tokio::test]
+async fn invalid_deletes_are_rejected() {
+    // I. Arrange
+    let app = TestApp::new(TestApp::init_repo_data()).await;
+    let requests = [
+        app.delete_user(9),
+        app.purge_user(9),
+        app.delete_user(1),
+        app.delete_user(1),
+        app.purge_user(1),
+        app.purge_user(1),
+    ];
+
+    // II. Act
+    let mut responses = Vec::new();
+    for req in requests {
+        let response = app.router().oneshot(req).await.unwrap();
+        responses.push(response.status().as_u16());
+    }
+
+    // III. Assert
+    assert_eq!(vec![404, 404, 200, 404, 204, 404], responses);
+}
+
+#[tokio::test]
+async fn retried_delete_is_replayed() {
+    // I. Arrange
+    let app = TestApp::new(TestApp::init_repo_data()).await;
+    let delete = || TestApp::with_idempotency(app.delete_user(1), 1);
+    let purge = || TestApp::with_idempotency(app.purge_user(2), 2);
+    let (first, retry) = (delete(), delete());
+    let (first_purge, retried_purge) = (purge(), purge());
+    let router = app.router();
+    let UserApi { mut cache_manager, .. } = app.app;
+    spawn(async move { cache_manager.run().await });
+
+    // II. Act
+    let first = router.clone().oneshot(first).await.unwrap();
+    let retry = router.clone().oneshot(retry).await.unwrap();
+    let first_purge = router.clone().oneshot(first_purge).await.unwrap();
+    let retried_purge = router.oneshot(retried_purge).await.unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::OK, first.status());
+    assert_eq!(StatusCode::OK, retry.status());
+    assert_eq!("true", retry.headers()["Idempotent-Replayed"]);
+    assert_eq!(body(first).await, body(retry).await);
+    assert_eq!(StatusCode::NO_CONTENT, first_purge.status());
+    assert_eq!(StatusCode::NO_CONTENT, retried_purge.status());
+    assert_eq!("true", retried_purge.headers()["Idempotent-Replayed"]);
+}
+
+#[tokio::test]
+async fn key_reused_to_purge_is_422() {
+    // I. Arrange
+    let app = TestApp::new(TestApp::init_repo_data()).await;
+    let delete = TestApp::with_idempotency(app.delete_user(1), 1);
+    let purge = TestApp::with_idempotency(app.purge_user(1), 1);
+    let router = app.router();
+    let UserApi { mut cache_manager, .. } = app.app;
+    spawn(async move { cache_manager.run().await });
+
+    // II. Act
+    let delete = router.clone().oneshot(delete).await.unwrap();
+    let purge = router.oneshot(purge).await.unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::OK, delete.status());
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, purge.status());
+}
+
+#[tokio::test]
+async fn email_is_reusable_once_deleted() {
+    // I. Arrange
+    let app = TestApp::new(TestApp::init_repo_data()).await;
+    let first = NewUser::new("first@email".to_string());
+
+    // II. Act
+    let deleted = app.router().oneshot(app.delete_user(1)).await.unwrap();
+    let created = app.router().oneshot(app.post_user(&first)).await.unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::OK, deleted.status());
+    assert_eq!(StatusCode::OK, created.status());
+    assert_eq!(6, body(created).await["id"]);
+}
+
+#[tokio::test]
+async fn email_is_reusable_once_purged() {
+    // I. Arrange
+    let users = TestApp::init_repo_data().with_email_reuse(EmailReuse::OnPurge);
+    let app = TestApp::new(users).await;
+    let first = NewUser::new("first@email".to_string());
+
+    // II. Act
+    let deleted = app.router().oneshot(app.delete_user(1)).await.unwrap();
+    let taken = app.router().oneshot(app.post_user(&first)).await.unwrap();
+    let purged = app.router().oneshot(app.purge_user(1)).await.unwrap();
+    let created = app.router().oneshot(app.post_user(&first)).await.unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::OK, deleted.status());
+    assert_eq!(StatusCode::CONFLICT, taken.status());
+    assert_eq!(StatusCode::NO_CONTENT, purged.status());
+    assert_eq!(StatusCode::OK, created.status());
+}
+
+#[tokio::test]
+async fn purged_id_is_not_reused() {
+    // I. Arrange
+    let app = TestApp::new(TestApp::init_repo_data()).await;
+    let sixth = NewUser::new("sixth@email".to_string());
+
+    // II. Act
+    let purged = app.router().oneshot(app.purge_user(5)).await.unwrap();
+    let created = app.router().oneshot(app.post_user(&sixth)).await.unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::NO_CONTENT, purged.status());
+    assert_eq!(6, body(created).await["id"]);
+}
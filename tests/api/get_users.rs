@@ -1,7 +1,12 @@
 use crate::test_app::TestApp;
+use lib::user::User;
 use lib::warehouse::UserRepository;
 
+use axum::response::Response;
 use hyper::body::to_bytes as BodyToBytes;
+use hyper::header;
+use hyper::Body;
+use hyper::Request;
 use hyper::StatusCode;
 use serde_json::Value;
 use tower::ServiceExt;
@@ -52,3 +57,116 @@ async fn with_some_users() {
     let actual_body: Value = serde_json::from_slice(&actual_body).unwrap();
     insta::assert_json_snapshot!(&actual_body);
 }
+
+async fn ids(response: Response) -> Vec<u64> {
+    let body = BodyToBytes(response.into_body()).await.unwrap();
+    let users: Vec<User> = serde_json::from_slice(&body).unwrap();
+    users.into_iter().map(|user| user.id).collect()
+}
+
+/// The target of the `rel="next"` link of the `response`, if any.
+fn next(response: &Response) -> Option<String> {
+    let link = response.headers().get(header::LINK)?.to_str().unwrap();
+    let target = link.strip_suffix(">; rel=\"next\"").unwrap().strip_prefix('<').unwrap();
+    Some(target.to_string())
+}
+
+#[tokio::test]
+async fn pages_are_linked() {
+    // I. Arrange
+    let app = TestApp::new(TestApp::init_repo_data()).await;
+    let mut req = app.list_users("limit=2");
+
+    // II. Act
+    let mut pages = Vec::new();
+    loop {
+        let response = app.router().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        let link = next(&response);
+        pages.push(ids(response).await);
+        match link {
+            Some(link) => req = Request::builder().uri(link).body(Body::empty()).unwrap(),
+            None => break,
+        }
+    }
+
+    // III. Assert
+    assert_eq!(vec![vec![1, 2], vec![3, 4], vec![5]], pages);
+}
+
+#[tokio::test]
+async fn users_are_sorted_and_filtered() {
+    // I. Arrange
+    let app = TestApp::new(TestApp::init_repo_data()).await;
+    let queries = [
+        "sort=email",
+        "sort=email&order=desc",
+        "sort=created_at&order=desc",
+        "email_prefix=f",
+        "email_contains=th&order=desc",
+        "email_prefix=f&email_contains=th&sort=email",
+    ];
+
+    // II. Act
+    let mut pages = Vec::new();
+    for query in queries {
+        let response = app.router().oneshot(app.list_users(query)).await.unwrap();
+        pages.push(ids(response).await);
+    }
+
+    // III. Assert
+    let expected = vec![
+        vec![5, 1, 4, 2, 3],
+        vec![3, 2, 4, 1, 5],
+        vec![5, 4, 3, 2, 1],
+        vec![1, 4, 5],
+        vec![5, 4, 3],
+        vec![5, 4],
+    ];
+    assert_eq!(expected, pages);
+}
+
+#[tokio::test]
+async fn sorted_pages_are_linked() {
+    // I. Arrange
+    let app = TestApp::new(TestApp::init_repo_data()).await;
+    let first = app.list_users("sort=email&order=desc&limit=3&email_contains=%40");
+
+    // II. Act
+    let first = app.router().oneshot(first).await.unwrap();
+    let link = next(&first).unwrap();
+    let second = Request::builder().uri(&link).body(Body::empty()).unwrap();
+    let second = app.router().oneshot(second).await.unwrap();
+
+    // III. Assert
+    assert!(link.starts_with("/users?limit=3&sort=email&order=desc&email_contains=%40&cursor="));
+    assert_eq!(vec![3, 2, 4], ids(first).await);
+    assert_eq!(None, next(&second));
+    assert_eq!(vec![1, 5], ids(second).await);
+}
+
+#[tokio::test]
+async fn invalid_queries_are_rejected() {
+    // I. Arrange
+    let app = TestApp::new(TestApp::init_repo_data()).await;
+    let page = app.router().oneshot(app.list_users("sort=email&limit=1")).await.unwrap();
+    let by_email = next(&page).unwrap();
+    let cursor = by_email.rsplit("cursor=").next().unwrap();
+    let queries = [
+        "limit=0".to_string(),
+        "limit=1001".to_string(),
+        "cursor=garbage".to_string(),
+        format!("sort=id&cursor={cursor}"),
+        "sort=name".to_string(),
+    ];
+
+    // II. Act
+    let mut responses = Vec::new();
+    for query in queries {
+        let response = app.router().oneshot(app.list_users(&query)).await.unwrap();
+        responses.push(response.status().as_u16());
+    }
+
+    // III. Assert
+    assert_eq!(vec![400, 400, 400, 400, 400], responses);
+}
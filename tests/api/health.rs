@@ -0,0 +1,158 @@
+use crate::test_app::TestApp;
+use lib::fingerprint::Fingerprint;
+use lib::ikey::IKey;
+use lib::middleware::cache::IdempotencyConfig;
+use lib::server::UserApi;
+use lib::user::NewUser;
+use lib::warehouse::Cache;
+use lib::warehouse::CacheError;
+use lib::warehouse::CachedResponse;
+use lib::warehouse::IdempotencyStore;
+use lib::warehouse::Lock;
+use lib::warehouse::UserRepository;
+
+use axum::Router;
+use hyper::body::to_bytes as BodyToBytes;
+use hyper::Body;
+use hyper::Request;
+use hyper::StatusCode;
+use serde_json::Value;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::spawn;
+use tokio::time::sleep;
+use tower::ServiceExt;
+
+async fn probe(router: Router, path: &str) -> (StatusCode, Value) {
+    let req = Request::builder().uri(path).body(Body::empty()).unwrap();
+    let response = router.oneshot(req).await.unwrap();
+    let status = response.status();
+    let body = BodyToBytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+/// A store that takes its time to lock keys, as if overloaded.
+#[derive(Clone, Debug)]
+struct SlowStore(Cache);
+
+#[axum::async_trait]
+impl IdempotencyStore for SlowStore {
+    async fn get(&mut self, key: &IKey) -> Result<CachedResponse, CacheError> {
+        self.0.get(key).await
+    }
+
+    async fn set(&mut self, key: &IKey, res: &CachedResponse) -> Result<(), CacheError> {
+        self.0.set(key, res).await
+    }
+
+    async fn lock(&mut self, key: &IKey, fingerprint: &Fingerprint) -> Result<Lock, CacheError> {
+        sleep(Duration::from_millis(500)).await;
+        self.0.lock(key, fingerprint).await
+    }
+
+    async fn delete(&mut self, key: &IKey) -> Result<(), CacheError> {
+        self.0.delete(key).await
+    }
+
+    async fn expire(&mut self) -> Result<usize, CacheError> {
+        self.0.expire().await
+    }
+
+    async fn size(&mut self) -> Result<usize, CacheError> {
+        self.0.size().await
+    }
+}
+
+#[tokio::test]
+async fn healthz_is_ok() {
+    // I. Arrange
+    let app = TestApp::new(UserRepository::new()).await;
+
+    // II. Act
+    let (status, body) = probe(app.router(), "/healthz").await;
+
+    // III. Assert
+    assert_eq!(StatusCode::OK, status);
+    insta::assert_json_snapshot!(body);
+}
+
+#[tokio::test]
+async fn readyz_is_ok_with_running_cache() {
+    // I. Arrange
+    let app = TestApp::new(UserRepository::new()).await;
+    let UserApi { api, mut cache_manager, .. } = app.app;
+    spawn(async move { cache_manager.run().await });
+
+    // II. Act
+    let (status, body) = probe(api, "/readyz").await;
+
+    // III. Assert
+    assert_eq!(StatusCode::OK, status);
+    insta::assert_json_snapshot!(body);
+}
+
+#[tokio::test]
+async fn readyz_is_503_without_cache_manager() {
+    // I. Arrange
+    let app = TestApp::new(UserRepository::new()).await;
+    let UserApi { api, cache_manager, .. } = app.app;
+    drop(cache_manager);
+
+    // II. Act
+    let (status, body) = probe(api, "/readyz").await;
+
+    // III. Assert
+    assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status);
+    insta::assert_json_snapshot!(body);
+}
+
+#[tokio::test]
+async fn livez_is_503_with_stalled_cache() {
+    // I. Arrange
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let timeout = Duration::from_millis(50);
+    let config = IdempotencyConfig { timeout, ..Default::default() };
+    // The manager is kept, but never run.
+    let UserApi { api, cache_manager: _cache_manager, .. } =
+        UserApi::with_idempotency(listener, UserRepository::new(), config);
+
+    // II. Act
+    let (status, body) = probe(api, "/livez").await;
+
+    // III. Assert
+    assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status);
+    insta::assert_json_snapshot!(body);
+}
+
+#[tokio::test]
+async fn livez_is_ok_with_busy_cache() {
+    // I. Arrange
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let timeout = Duration::from_millis(50);
+    let config = IdempotencyConfig { timeout, shards: 1, mailbox: 1, ..Default::default() };
+    let UserApi { api, mut cache_manager, .. } =
+        UserApi::with_stores(listener, UserRepository::new(), config, |_| SlowStore(Cache::new()));
+    spawn(async move { cache_manager.run().await });
+    // The requests queue up behind a slow lock, filling the mailbox.
+    for key in 1..=3 {
+        let new_user = NewUser::new(format!("user{key}@email"));
+        let req = Request::builder()
+            .method("POST")
+            .uri("/users")
+            .header("Content-Type", "application/json")
+            .header("Idempotency-Key", key)
+            .body(Body::from(serde_json::to_vec(&new_user).unwrap()))
+            .unwrap();
+        spawn(api.clone().oneshot(req));
+    }
+    sleep(Duration::from_millis(20)).await;
+
+    // II. Act
+    let (live, _) = probe(api.clone(), "/livez").await;
+    let (ready, _) = probe(api, "/readyz").await;
+
+    // III. Assert
+    assert_eq!(StatusCode::OK, live);
+    assert_eq!(StatusCode::SERVICE_UNAVAILABLE, ready);
+}
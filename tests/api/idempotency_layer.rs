@@ -0,0 +1,370 @@
+use lib::fingerprint::Fingerprint;
+use lib::ikey::IKey;
+use lib::middleware::cache::manager::ShardedCacheManager;
+use lib::middleware::cache::CacheBackend;
+use lib::middleware::cache::FailurePolicy;
+use lib::middleware::cache::IdempotencyConfig;
+use lib::middleware::cache::IdempotencyLayer;
+use lib::warehouse::Cache;
+use lib::warehouse::CacheError;
+use lib::warehouse::CachedResponse;
+use lib::warehouse::IdempotencyStore;
+use lib::warehouse::Lock;
+
+use axum::routing::put;
+use axum::Router;
+use color_eyre::eyre::eyre;
+use hyper::body::to_bytes as BodyToBytes;
+use hyper::http::HeaderValue;
+use hyper::Body;
+use hyper::Method;
+use hyper::Request;
+use hyper::StatusCode;
+use serde_json::Value;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::spawn;
+use tower::ServiceExt;
+
+/// A router counting the `PUT` requests that reach its handler.
+fn counting_router(config: IdempotencyConfig) -> (Router, Arc<AtomicU64>) {
+    let shard = config.cache.shard(config.shards);
+    counting_router_with(config, |_| Cache::with_config(&shard))
+}
+
+/// A [counting_router] caching the responses of shard `i` in the store
+/// `make(i)`.
+fn counting_router_with<S: IdempotencyStore>(
+    config: IdempotencyConfig,
+    make: impl FnMut(usize) -> S,
+) -> (Router, Arc<AtomicU64>) {
+    let (idempotency, mut cache_manager) = IdempotencyLayer::with_stores(config, make);
+    spawn(async move { cache_manager.run().await });
+    counting(idempotency)
+}
+
+/// A [counting_router] whose [ShardedCacheManager] is never run, so every call
+/// to the cache times out.
+fn stalled_router(config: IdempotencyConfig) -> (Router, Arc<AtomicU64>, ShardedCacheManager) {
+    let (idempotency, cache_manager) = IdempotencyLayer::new(config);
+    let (router, calls) = counting(idempotency);
+    (router, calls, cache_manager)
+}
+
+/// A [counting_router] whose handler takes `delay` to respond after counting.
+fn slow_router(config: IdempotencyConfig, delay: Duration) -> (Router, Arc<AtomicU64>) {
+    let (idempotency, mut cache_manager) = IdempotencyLayer::new(config);
+    spawn(async move { cache_manager.run().await });
+    let calls = Arc::new(AtomicU64::new(0));
+    let counter = calls.clone();
+    let handler = move || async move {
+        let count = counter.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(delay).await;
+        count.to_string()
+    };
+    let router = Router::new().route("/count", put(handler));
+    (router.route_layer(idempotency), calls)
+}
+
+fn counting(idempotency: IdempotencyLayer) -> (Router, Arc<AtomicU64>) {
+    let calls = Arc::new(AtomicU64::new(0));
+    let counter = calls.clone();
+    let handler = move || async move { counter.fetch_add(1, Ordering::SeqCst).to_string() };
+    let router = Router::new().route("/count", put(handler).get(|| async { "get" }));
+    (router.route_layer(idempotency), calls)
+}
+
+/// A store that locks keys, but fails to record any response.
+#[derive(Clone, Debug)]
+struct FailingStore(Cache);
+
+#[axum::async_trait]
+impl IdempotencyStore for FailingStore {
+    async fn get(&mut self, key: &IKey) -> Result<CachedResponse, CacheError> {
+        self.0.get(key).await
+    }
+
+    async fn set(&mut self, _: &IKey, _: &CachedResponse) -> Result<(), CacheError> {
+        Err(eyre!("Disk Is Full").into())
+    }
+
+    async fn lock(&mut self, key: &IKey, fingerprint: &Fingerprint) -> Result<Lock, CacheError> {
+        self.0.lock(key, fingerprint).await
+    }
+
+    async fn delete(&mut self, key: &IKey) -> Result<(), CacheError> {
+        self.0.delete(key).await
+    }
+
+    async fn expire(&mut self) -> Result<usize, CacheError> {
+        self.0.expire().await
+    }
+
+    async fn size(&mut self) -> Result<usize, CacheError> {
+        self.0.size().await
+    }
+}
+
+fn request(method: Method, key: Option<u64>) -> Request<Body> {
+    let mut req = Request::builder().method(method).uri("/count").body(Body::empty()).unwrap();
+    if let Some(key) = key {
+        req.headers_mut().insert("Idempotency-Key", HeaderValue::from(key));
+    }
+    req
+}
+
+fn put_config() -> IdempotencyConfig {
+    IdempotencyConfig { methods: vec![Method::PUT], require_key: true, ..Default::default() }
+}
+
+#[tokio::test]
+async fn configured_method_is_replayed() {
+    // I. Arrange
+    let (router, calls) = counting_router(put_config());
+
+    // II. Act
+    let original = router.clone().oneshot(request(Method::PUT, Some(1))).await.unwrap();
+    let duplicate = router.clone().oneshot(request(Method::PUT, Some(1))).await.unwrap();
+
+    // III. Assert
+    assert_eq!(1, calls.load(Ordering::SeqCst));
+    assert_eq!(StatusCode::OK, duplicate.status());
+    assert_eq!("true", duplicate.headers().get("Idempotent-Replayed").unwrap());
+
+    let original = BodyToBytes(original.into_body()).await.unwrap();
+    let duplicate = BodyToBytes(duplicate.into_body()).await.unwrap();
+    assert_eq!(original, duplicate);
+}
+
+#[tokio::test]
+async fn missing_required_key_is_400() {
+    // I. Arrange
+    let (router, calls) = counting_router(put_config());
+
+    // II. Act
+    let response = router.oneshot(request(Method::PUT, None)).await.unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::BAD_REQUEST, response.status());
+    assert_eq!(0, calls.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn invalid_key_is_400() {
+    // I. Arrange
+    let config = IdempotencyConfig { require_key: false, ..put_config() };
+    let (router, calls) = counting_router(config);
+    let keys = [
+        HeaderValue::from_static(""),
+        HeaderValue::try_from("k".repeat(IKey::MAX_LEN as usize)).unwrap(),
+        HeaderValue::from_bytes(b"caf\xc3\xa9").unwrap(),
+    ];
+
+    // II. Act
+    let mut responses = Vec::new();
+    for key in keys {
+        let mut req = request(Method::PUT, None);
+        req.headers_mut().insert("Idempotency-Key", key);
+        responses.push(router.clone().oneshot(req).await.unwrap().status());
+    }
+
+    // III. Assert
+    assert_eq!(vec![StatusCode::BAD_REQUEST; 3], responses);
+    assert_eq!(0, calls.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn other_methods_pass_through() {
+    // I. Arrange
+    let (router, _) = counting_router(put_config());
+
+    // II. Act
+    let response = router.oneshot(request(Method::GET, None)).await.unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::OK, response.status());
+    assert!(response.headers().get("Idempotent-Replayed").is_none());
+}
+
+#[tokio::test]
+async fn failed_cache_update_returns_response_and_releases_key() {
+    // I. Arrange
+    let (router, calls) = counting_router_with(put_config(), |_| FailingStore(Cache::new()));
+
+    // II. Act
+    let original = router.clone().oneshot(request(Method::PUT, Some(1))).await.unwrap();
+    let retried = router.oneshot(request(Method::PUT, Some(1))).await.unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::OK, original.status());
+    assert_eq!(StatusCode::OK, retried.status());
+    assert_eq!(2, calls.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn stalled_cache_fails_closed_with_503() {
+    // I. Arrange
+    let timeout = Duration::from_millis(50);
+    let config = IdempotencyConfig { timeout, ..put_config() };
+    let (router, calls, _cache_manager) = stalled_router(config);
+
+    // II. Act
+    let response = router.oneshot(request(Method::PUT, Some(1))).await.unwrap();
+
+    // III. Assert
+    assert_eq!(0, calls.load(Ordering::SeqCst));
+    assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+
+    let body = BodyToBytes(response.into_body()).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    insta::assert_json_snapshot!(&body);
+}
+
+#[tokio::test]
+async fn stalled_cache_fails_open_without_idempotency() {
+    // I. Arrange
+    let timeout = Duration::from_millis(50);
+    let on_failure = FailurePolicy::Open;
+    let config = IdempotencyConfig { timeout, on_failure, ..put_config() };
+    let (router, calls, _cache_manager) = stalled_router(config);
+
+    // II. Act
+    let original = router.clone().oneshot(request(Method::PUT, Some(1))).await.unwrap();
+    let duplicate = router.oneshot(request(Method::PUT, Some(1))).await.unwrap();
+
+    // III. Assert
+    assert_eq!(2, calls.load(Ordering::SeqCst));
+    assert_eq!(StatusCode::OK, original.status());
+    assert_eq!(StatusCode::OK, duplicate.status());
+    assert!(duplicate.headers().get("Idempotent-Replayed").is_none());
+}
+
+#[tokio::test]
+async fn lock_timed_out_is_released() {
+    // I. Arrange
+    let timeout = Duration::from_millis(50);
+    let config = IdempotencyConfig { timeout, ..put_config() };
+    let (router, calls, mut cache_manager) = stalled_router(config);
+    let timed_out = router.clone().oneshot(request(Method::PUT, Some(1))).await.unwrap();
+    // The lock is only processed once the requester has stopped waiting.
+    spawn(async move { cache_manager.run().await });
+
+    // II. Act
+    let retried = router.oneshot(request(Method::PUT, Some(1))).await.unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::SERVICE_UNAVAILABLE, timed_out.status());
+    assert_eq!(StatusCode::OK, retried.status());
+    assert_eq!(1, calls.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn dropped_request_is_released() {
+    // I. Arrange
+    let (router, calls) = slow_router(put_config(), Duration::from_millis(300));
+    let dropped = router.clone().oneshot(request(Method::PUT, Some(1)));
+    let dropped = tokio::time::timeout(Duration::from_millis(50), dropped).await;
+    // The key is released on a task of its own, once the request is dropped.
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // II. Act
+    let retried = router.oneshot(request(Method::PUT, Some(1))).await.unwrap();
+
+    // III. Assert
+    assert!(dropped.is_err());
+    assert_eq!(StatusCode::OK, retried.status());
+    assert_eq!(2, calls.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn failed_cache_update_fails_open_with_response() {
+    // I. Arrange
+    let config = IdempotencyConfig { on_failure: FailurePolicy::Open, ..put_config() };
+    let (router, calls) = counting_router_with(config, |_| FailingStore(Cache::new()));
+
+    // II. Act
+    let response = router.oneshot(request(Method::PUT, Some(1))).await.unwrap();
+
+    // III. Assert
+    assert_eq!(1, calls.load(Ordering::SeqCst));
+    assert_eq!(StatusCode::OK, response.status());
+}
+
+#[tokio::test]
+async fn keys_on_every_shard_are_replayed() {
+    // I. Arrange
+    let config = IdempotencyConfig { shards: 4, mailbox: 1, ..put_config() };
+    let (router, calls) = counting_router(config);
+    let keys = 1..=32;
+
+    // II. Act
+    let originals: Vec<_> = keys
+        .clone()
+        .map(|key| spawn(router.clone().oneshot(request(Method::PUT, Some(key)))))
+        .collect();
+    for original in originals {
+        assert_eq!(StatusCode::OK, original.await.unwrap().unwrap().status());
+    }
+    let mut duplicates = Vec::new();
+    for key in keys {
+        duplicates.push(router.clone().oneshot(request(Method::PUT, Some(key))).await.unwrap());
+    }
+
+    // III. Assert
+    assert_eq!(32, calls.load(Ordering::SeqCst));
+    for duplicate in duplicates {
+        assert_eq!("true", duplicate.headers().get("Idempotent-Replayed").unwrap());
+    }
+}
+
+#[tokio::test]
+async fn direct_backend_is_replayed() {
+    // I. Arrange
+    let config = IdempotencyConfig { backend: CacheBackend::Direct, ..put_config() };
+    let (router, calls) = counting_router(config);
+
+    // II. Act
+    let original = router.clone().oneshot(request(Method::PUT, Some(1))).await.unwrap();
+    let duplicate = router.clone().oneshot(request(Method::PUT, Some(1))).await.unwrap();
+
+    // III. Assert
+    assert_eq!(1, calls.load(Ordering::SeqCst));
+    assert_eq!(StatusCode::OK, original.status());
+    assert_eq!("true", duplicate.headers().get("Idempotent-Replayed").unwrap());
+}
+
+#[tokio::test]
+async fn direct_backend_runs_concurrent_requests_with_key_once() {
+    // I. Arrange
+    let config = IdempotencyConfig { backend: CacheBackend::Direct, ..put_config() };
+    let (router, calls) = counting_router(config);
+
+    // II. Act
+    let responses: Vec<_> =
+        (0..16).map(|_| spawn(router.clone().oneshot(request(Method::PUT, Some(1))))).collect();
+    let mut statuses = Vec::new();
+    for response in responses {
+        statuses.push(response.await.unwrap().unwrap().status());
+    }
+
+    // III. Assert
+    assert_eq!(1, calls.load(Ordering::SeqCst));
+    assert!(statuses.iter().all(|s| [StatusCode::OK, StatusCode::CONFLICT].contains(s)));
+}
+
+#[tokio::test]
+async fn body_over_limit_is_413() {
+    // I. Arrange
+    let (router, calls) = counting_router(IdempotencyConfig { body_limit: 8, ..put_config() });
+    let mut req = request(Method::PUT, Some(1));
+    *req.body_mut() = Body::from("more than eight bytes");
+
+    // II. Act
+    let response = router.oneshot(req).await.unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, response.status());
+    assert_eq!(0, calls.load(Ordering::SeqCst));
+}
@@ -0,0 +1,82 @@
+use crate::test_app::TestApp;
+use lib::obs;
+use lib::obs::LogFormat;
+use lib::obs::LogRotation;
+use lib::server::UserApi;
+use lib::warehouse::UserRepository;
+
+use hyper::http::HeaderValue;
+use hyper::StatusCode;
+use serde_json::Value;
+use std::fs;
+use tempfile::TempDir;
+use tokio::spawn;
+use tower::ServiceExt;
+use tracing::instrument::WithSubscriber;
+use tracing::Dispatch;
+
+#[tokio::test]
+async fn json_logs_carry_request_fields_on_every_line() {
+    // I. Arrange
+    let dir = TempDir::new().unwrap();
+    let sub =
+        obs::get_sub_with(LogFormat::Json, "info", Some(dir.path()), LogRotation::Never, None);
+    let app = TestApp::new(UserRepository::new()).await;
+    let mut req = TestApp::with_idempotency(app.post_user(&app.test_user), 7);
+    req.headers_mut().insert(obs::REQUEST_ID_HEADER, HeaderValue::from_static("req-1"));
+    let router = app.router();
+    let UserApi { mut cache_manager, .. } = app.app;
+    spawn(async move { cache_manager.run().await });
+
+    // II. Act
+    let response = router.oneshot(req).with_subscriber(sub.unwrap()).await.unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::OK, response.status());
+    let logs = fs::read_to_string(dir.path().join("icapi.log")).unwrap();
+    let lines: Vec<Value> = logs.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert!(!lines.is_empty());
+    for line in &lines {
+        let request = &line["spans"][0];
+        assert_eq!("request", request["name"], "{line}");
+        assert_eq!("req-1", request["request_id"], "{line}");
+        assert_eq!("7", request["idempotency_key"], "{line}");
+    }
+    let created = lines.iter().find(|line| line["fields"]["message"] == "New User Created");
+    assert!(created.unwrap()["spans"][0]["user_id"].is_string());
+}
+
+#[tokio::test]
+async fn cache_manager_logs_correlate_with_request() {
+    // I. Arrange
+    let dir = TempDir::new().unwrap();
+    let sub =
+        obs::get_sub_with(LogFormat::Json, "info", Some(dir.path()), LogRotation::Never, None);
+    let dispatch = Dispatch::new(sub.unwrap());
+    let app = TestApp::new(UserRepository::new()).await;
+    let mut req = TestApp::with_idempotency(app.post_user(&app.test_user), 7);
+    req.headers_mut().insert(obs::REQUEST_ID_HEADER, HeaderValue::from_static("req-2"));
+    let router = app.router();
+    let UserApi { mut cache_manager, .. } = app.app;
+    // The manager logs from its own task, outside of any request.
+    let manager = async move { cache_manager.run().await };
+    spawn(manager.with_subscriber(dispatch.clone()));
+
+    // II. Act
+    let response = router.oneshot(req).with_subscriber(dispatch).await.unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::OK, response.status());
+    let logs = fs::read_to_string(dir.path().join("icapi.log")).unwrap();
+    let lines: Vec<Value> = logs.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    let processed = |line: &&Value| {
+        let message = line["fields"]["message"].as_str().unwrap_or_default();
+        line["target"].as_str().unwrap().ends_with("manager") && message.starts_with("Processing")
+    };
+    let processed: Vec<&Value> = lines.iter().filter(processed).collect();
+    // LOCK and SET.
+    assert_eq!(2, processed.len(), "{logs}");
+    for line in processed {
+        assert_eq!("req-2", line["spans"][0]["request_id"], "{line}");
+    }
+}
@@ -1,9 +1,50 @@
-#![feature(lazy_cell)]
 #[cfg(test)]
 mod get_users;
 
+#[cfg(test)]
+mod delete_user;
+
 #[cfg(test)]
 mod create_user;
 
+#[cfg(test)]
+mod cache;
+
+#[cfg(test)]
+mod cli;
+
+#[cfg(test)]
+mod config;
+
+#[cfg(test)]
+mod health;
+
+#[cfg(test)]
+mod idempotency_layer;
+
+#[cfg(test)]
+mod logs;
+
+#[cfg(test)]
+mod metrics;
+
+#[cfg(test)]
+mod otlp;
+
+#[cfg(test)]
+mod request_id;
+
+#[cfg(test)]
+mod shutdown;
+
+#[cfg(test)]
+mod sqlite_cache;
+
+#[cfg(test)]
+mod sqlite_users;
+
 #[cfg(test)]
 mod test_app;
+
+#[cfg(test)]
+mod update_user;
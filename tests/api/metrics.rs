@@ -0,0 +1,95 @@
+use crate::test_app::TestApp;
+use lib::middleware::cache::manager::Heartbeat;
+use lib::middleware::cache::IdempotencyConfig;
+use lib::server::UserApi;
+use lib::warehouse::UserRepository;
+
+use axum::Router;
+use hyper::body::to_bytes as BodyToBytes;
+use hyper::header;
+use hyper::Body;
+use hyper::Request;
+use hyper::StatusCode;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::spawn;
+use tower::ServiceExt;
+
+async fn scrape(router: Router) -> String {
+    let req = Request::builder().uri("/metrics").body(Body::empty()).unwrap();
+    let response = router.oneshot(req).await.unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+    let content_type = response.headers()[header::CONTENT_TYPE].to_str().unwrap();
+    assert_eq!(prometheus::TEXT_FORMAT, content_type);
+    let body = BodyToBytes(response.into_body()).await.unwrap();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+/// The sum of the samples of `metric` across its labels.
+fn total(metrics: &str, metric: &str) -> i64 {
+    metrics
+        .lines()
+        .filter(|line| line.starts_with(&format!("{metric}{{")))
+        .map(|line| line.rsplit(' ').next().unwrap().parse::<i64>().unwrap())
+        .sum()
+}
+
+#[tokio::test]
+async fn metrics_count_requests_and_replays() {
+    // I. Arrange
+    let app = TestApp::new(UserRepository::new()).await;
+    let req = || TestApp::with_idempotency(app.post_user(&app.test_user), 1);
+    let (first, replay) = (req(), req());
+    let router = app.router();
+    let UserApi { mut cache_manager, .. } = app.app;
+    spawn(async move { cache_manager.run().await });
+
+    // II. Act
+    let first = router.clone().oneshot(first).await.unwrap();
+    let replay = router.clone().oneshot(replay).await.unwrap();
+    // The managers sample their store on their next heartbeat.
+    tokio::time::sleep(Heartbeat::EVERY).await;
+    let metrics = scrape(router).await;
+
+    // III. Assert
+    assert_eq!(StatusCode::OK, first.status());
+    assert_eq!(StatusCode::OK, replay.status());
+    let requests = r#"http_requests_total{method="POST",route="/users",status="200"} 2"#;
+    assert!(metrics.contains(requests), "{metrics}");
+    assert!(metrics.contains(r#"idempotency_lookups_total{outcome="miss"} 1"#), "{metrics}");
+    assert!(metrics.contains(r#"idempotency_lookups_total{outcome="hit"} 1"#), "{metrics}");
+    assert!(metrics.contains(r#"idempotency_replays_total{status="200"} 1"#), "{metrics}");
+    assert!(metrics.contains("http_request_duration_seconds_bucket"), "{metrics}");
+    assert_eq!(1, total(&metrics, "idempotency_cache_entries"));
+    assert_eq!(0, total(&metrics, "idempotency_mailbox_depth"));
+    assert_eq!(
+        IdempotencyConfig::DEFAULT_SHARDS,
+        metrics.matches("idempotency_mailbox_depth{").count()
+    );
+}
+
+#[tokio::test]
+async fn metrics_count_unavailable_cache() {
+    // I. Arrange
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let timeout = Duration::from_millis(50);
+    let config = IdempotencyConfig { timeout, ..Default::default() };
+    // The manager is kept, but never run.
+    let UserApi { api, cache_manager: _cache_manager, .. } =
+        UserApi::with_idempotency(listener, TestApp::init_repo_data(), config);
+    let app = TestApp::new(UserRepository::new()).await;
+    let req = TestApp::with_idempotency(app.post_user(&app.test_user), 1);
+
+    // II. Act
+    let response = api.clone().oneshot(req).await.unwrap();
+    let metrics = scrape(api).await;
+
+    // III. Assert
+    assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+    assert!(metrics.contains(r#"idempotency_lookups_total{outcome="unavailable"} 1"#));
+    let requests = r#"http_requests_total{method="POST",route="/users",status="503"} 1"#;
+    assert!(metrics.contains(requests), "{metrics}");
+    // The stalled cache is not sampled, but the scrape still succeeds.
+    assert!(!metrics.contains("idempotency_cache_entries{"), "{metrics}");
+}
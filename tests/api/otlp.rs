@@ -0,0 +1,92 @@
+use crate::test_app::TestApp;
+use lib::obs;
+use lib::obs::LogFormat;
+use lib::obs::LogRotation;
+use lib::server::UserApi;
+use lib::warehouse::UserRepository;
+
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::Router;
+use axum::Server;
+use hyper::http::HeaderValue;
+use hyper::StatusCode;
+use serde_json::Value;
+use std::net::Ipv4Addr;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use tempfile::TempDir;
+use tokio::spawn;
+use tokio::task::spawn_blocking;
+use tower::ServiceExt;
+use tracing::instrument::WithSubscriber;
+
+type Received = Arc<Mutex<Vec<Value>>>;
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+const PARENT_ID: &str = "00f067aa0ba902b7";
+
+/// An in-process stand-in for an OTLP/HTTP collector, keeping the exported
+/// requests it receives.
+fn collector() -> (SocketAddr, Received) {
+    let received = Received::default();
+    let receive = |State(received): State<Received>, body: Bytes| async move {
+        received.lock().unwrap().push(serde_json::from_slice(&body).unwrap());
+        StatusCode::OK
+    };
+    let router = Router::new().fallback(receive).with_state(received.clone());
+    let server =
+        Server::bind(&SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).serve(router.into_make_service());
+    let addr = server.local_addr();
+    spawn(server);
+    (addr, received)
+}
+
+/// Every span exported in the `received` requests.
+fn spans(received: &Received) -> Vec<Value> {
+    let received = received.lock().unwrap();
+    let resource_spans = received.iter().flat_map(|req| req["resourceSpans"].as_array().unwrap());
+    let scope_spans = resource_spans.flat_map(|spans| spans["scopeSpans"].as_array().unwrap());
+    scope_spans.flat_map(|spans| spans["spans"].as_array().unwrap().clone()).collect()
+}
+
+#[tokio::test]
+async fn spans_are_exported_continuing_the_callers_trace() {
+    // I. Arrange
+    let (addr, received) = collector();
+    let provider = obs::otlp(&format!("http://{addr}/v1/traces"), "icapi-test").unwrap();
+    let dir = TempDir::new().unwrap();
+    let sub = obs::get_sub_with(
+        LogFormat::Json,
+        "info",
+        Some(dir.path()),
+        LogRotation::Never,
+        Some(obs::tracer(&provider)),
+    );
+    let app = TestApp::new(UserRepository::new()).await;
+    let mut req = TestApp::with_idempotency(app.post_user(&app.test_user), 1);
+    let traceparent = format!("00-{TRACE_ID}-{PARENT_ID}-01");
+    req.headers_mut().insert("traceparent", HeaderValue::from_str(&traceparent).unwrap());
+    let router = app.router();
+    let UserApi { mut cache_manager, .. } = app.app;
+    spawn(async move { cache_manager.run().await });
+
+    // II. Act
+    let response = router.oneshot(req).with_subscriber(sub.unwrap()).await.unwrap();
+    let status = response.status();
+    // The request span closes, and is exported, once the response is dropped.
+    drop(response);
+    spawn_blocking(move || provider.shutdown()).await.unwrap().unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::OK, status);
+    let spans = spans(&received);
+    let request = spans.iter().find(|span| span["name"] == "request").unwrap();
+    assert_eq!(TRACE_ID, request["traceId"], "{request}");
+    assert_eq!(PARENT_ID, request["parentSpanId"], "{request}");
+    // The spans of the idempotency layer are part of the same trace.
+    let lookup = spans.iter().find(|span| span["name"] == "Checking for Cached Response").unwrap();
+    assert_eq!(TRACE_ID, lookup["traceId"], "{lookup}");
+    assert_eq!(request["spanId"], lookup["parentSpanId"], "{lookup}");
+}
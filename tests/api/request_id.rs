@@ -0,0 +1,67 @@
+use crate::test_app::TestApp;
+use lib::obs::REQUEST_ID_HEADER;
+use lib::server::UserApi;
+use lib::warehouse::UserRepository;
+
+use hyper::http::HeaderValue;
+use hyper::Body;
+use hyper::Request;
+use hyper::StatusCode;
+use tokio::spawn;
+use tower::ServiceExt;
+
+#[tokio::test]
+async fn request_id_is_generated_and_echoed() {
+    // I. Arrange
+    let app = TestApp::new(UserRepository::new()).await;
+    let req = Request::builder().uri("/healthz").body(Body::empty()).unwrap();
+
+    // II. Act
+    let response = app.router().oneshot(req).await.unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::OK, response.status());
+    let request_id = response.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+    assert_eq!(36, request_id.len(), "{request_id} is not a UUID");
+}
+
+#[tokio::test]
+async fn request_id_is_accepted_and_echoed() {
+    // I. Arrange
+    let app = TestApp::new(UserRepository::new()).await;
+    let req = Request::builder()
+        .uri("/healthz")
+        .header(REQUEST_ID_HEADER, "client-id")
+        .body(Body::empty())
+        .unwrap();
+
+    // II. Act
+    let response = app.router().oneshot(req).await.unwrap();
+
+    // III. Assert
+    assert_eq!("client-id", response.headers()[REQUEST_ID_HEADER]);
+}
+
+#[tokio::test]
+async fn replayed_response_echoes_its_own_request_id() {
+    // I. Arrange
+    let app = TestApp::new(UserRepository::new()).await;
+    let req = |request_id: &'static str| {
+        let mut req = TestApp::with_idempotency(app.post_user(&app.test_user), 1);
+        req.headers_mut().insert(REQUEST_ID_HEADER, HeaderValue::from_static(request_id));
+        req
+    };
+    let (first, replay) = (req("first"), req("replay"));
+    let router = app.router();
+    let UserApi { mut cache_manager, .. } = app.app;
+    spawn(async move { cache_manager.run().await });
+
+    // II. Act
+    let first = router.clone().oneshot(first).await.unwrap();
+    let replay = router.oneshot(replay).await.unwrap();
+
+    // III. Assert
+    assert_eq!("first", first.headers()[REQUEST_ID_HEADER]);
+    assert_eq!("true", replay.headers()["Idempotent-Replayed"]);
+    assert_eq!("replay", replay.headers()[REQUEST_ID_HEADER]);
+}
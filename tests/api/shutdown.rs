@@ -0,0 +1,53 @@
+use crate::test_app::TestApp;
+use lib::server::UserApi;
+use lib::warehouse::UserRepository;
+
+use hyper::body::to_bytes as BodyToBytes;
+use hyper::StatusCode;
+use serde_json::Value;
+use tokio::spawn;
+use tokio::sync::oneshot;
+use tower::ServiceExt;
+
+#[tokio::test]
+async fn server_stops_after_shutdown_signal() {
+    // I. Arrange
+    let app = TestApp::new(UserRepository::new()).await;
+    let client = hyper::Client::new();
+    let before = TestApp::with_idempotency(app.post_user(&app.test_user), 1);
+    let after = app.get_users();
+    let (shutdown, signal) = oneshot::channel::<()>();
+    let server = spawn(app.run_until(async {
+        let _ = signal.await;
+    }));
+
+    // II. Act
+    let before = client.request(before).await.unwrap();
+    shutdown.send(()).unwrap();
+    let stopped = server.await.unwrap();
+    let after = client.request(after).await;
+
+    // III. Assert
+    assert_eq!(StatusCode::OK, before.status());
+    assert!(stopped.is_ok());
+    assert!(after.is_err());
+}
+
+#[tokio::test]
+async fn request_with_key_is_503_without_cache_manager() {
+    // I. Arrange
+    let app = TestApp::new(UserRepository::new()).await;
+    let req = TestApp::with_idempotency(app.post_user(&app.test_user), 1);
+    let UserApi { api, cache_manager, .. } = app.app;
+    drop(cache_manager);
+
+    // II. Act
+    let response = api.oneshot(req).await.unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::SERVICE_UNAVAILABLE, response.status());
+
+    let body = BodyToBytes(response.into_body()).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    insta::assert_json_snapshot!(&body);
+}
@@ -0,0 +1,169 @@
+use crate::cache::cached_response;
+use crate::cache::fingerprint;
+use crate::cache::ikey;
+use crate::test_app::TestApp;
+use lib::ikey::IKey;
+use lib::warehouse::CacheConfig;
+use lib::warehouse::Database;
+use lib::warehouse::IdempotencyStore;
+use lib::warehouse::Lock;
+use lib::warehouse::SqliteCache;
+use lib::warehouse::UserRepository;
+
+use hyper::StatusCode;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::spawn;
+
+fn open(dir: &TempDir, config: &CacheConfig) -> SqliteCache {
+    let db = Database::open(dir.path().join("icapi.db")).unwrap();
+    SqliteCache::new(db, config)
+}
+
+#[tokio::test]
+async fn lock_and_set_behave_like_memory_cache() {
+    // I. Arrange
+    let dir = TempDir::new().unwrap();
+    let mut cache = open(&dir, &CacheConfig::default());
+
+    // II. Act
+    let acquired = cache.lock(&ikey(), &fingerprint("first")).await.unwrap();
+    let in_flight = cache.lock(&ikey(), &fingerprint("first")).await.unwrap();
+    cache.set(&ikey(), &cached_response()).await.unwrap();
+    let completed = cache.lock(&ikey(), &fingerprint("first")).await.unwrap();
+    let mismatch = cache.lock(&ikey(), &fingerprint("second")).await.unwrap();
+
+    // III. Assert
+    assert!(matches!(acquired, Lock::Acquired));
+    assert!(matches!(in_flight, Lock::InFlight));
+    let Lock::Completed(cached) = completed else { panic!("Expected Completed, got {completed}") };
+    assert_eq!(cached_response().status, cached.status);
+    assert_eq!(cached_response().body, cached.body);
+    assert!(matches!(mismatch, Lock::Mismatch));
+}
+
+#[tokio::test]
+async fn responses_survive_reopening() {
+    // I. Arrange
+    let dir = TempDir::new().unwrap();
+    let mut cache = open(&dir, &CacheConfig::default());
+    cache.lock(&ikey(), &fingerprint("first")).await.unwrap();
+    cache.set(&ikey(), &cached_response()).await.unwrap();
+    drop(cache);
+
+    // II. Act
+    let mut reopened = open(&dir, &CacheConfig::default());
+    let cached = reopened.get(&ikey()).await.unwrap();
+
+    // III. Assert
+    assert_eq!(cached_response().body, cached.body);
+    assert_eq!(cached_response().fingerprint, cached.fingerprint);
+}
+
+#[tokio::test]
+async fn interrupted_request_is_released_on_reopening() {
+    // I. Arrange
+    let dir = TempDir::new().unwrap();
+    let mut cache = open(&dir, &CacheConfig::default());
+    cache.lock(&ikey(), &fingerprint("first")).await.unwrap();
+    // The request never completes, as if the server crashed.
+    drop(cache);
+
+    // II. Act
+    let mut reopened = open(&dir, &CacheConfig::default());
+    let retried = reopened.lock(&ikey(), &fingerprint("first")).await.unwrap();
+
+    // III. Assert
+    assert!(matches!(retried, Lock::Acquired));
+}
+
+#[tokio::test]
+async fn release_only_frees_own_locks() {
+    // I. Arrange
+    let dir = TempDir::new().unwrap();
+    let db = Database::open(dir.path().join("icapi.db")).unwrap();
+    let mut cache = SqliteCache::new(db.clone(), &CacheConfig::default());
+    let mut other = SqliteCache::new(db, &CacheConfig::default());
+    let other_key = IKey::try_from("other".to_string()).unwrap();
+    cache.lock(&ikey(), &fingerprint("first")).await.unwrap();
+    other.lock(&other_key, &fingerprint("first")).await.unwrap();
+
+    // II. Act
+    let released = cache.release().await.unwrap();
+
+    // III. Assert
+    assert_eq!(1, released);
+    let retried = cache.lock(&ikey(), &fingerprint("first")).await.unwrap();
+    assert!(matches!(retried, Lock::Acquired));
+    let held = cache.lock(&other_key, &fingerprint("first")).await.unwrap();
+    assert!(matches!(held, Lock::InFlight));
+}
+
+#[tokio::test]
+async fn expire_removes_expired_entries() {
+    // I. Arrange
+    let dir = TempDir::new().unwrap();
+    let config = CacheConfig { ttl: Duration::from_millis(10), ..CacheConfig::default() };
+    let mut cache = open(&dir, &config);
+    cache.lock(&ikey(), &fingerprint("first")).await.unwrap();
+    cache.set(&ikey(), &cached_response()).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    // II. Act
+    let miss = cache.get(&ikey()).await;
+    let expired = cache.expire().await.unwrap();
+
+    // III. Assert
+    assert!(miss.is_err());
+    assert_eq!(1, expired);
+}
+
+#[tokio::test]
+async fn shared_table_is_swept_and_counted_once() {
+    // I. Arrange
+    let dir = TempDir::new().unwrap();
+    let config = CacheConfig { ttl: Duration::from_millis(10), ..Default::default() };
+    let cache = open(&dir, &config);
+    let mut shards: Vec<_> = (0..4).map(|i| cache.shard(i)).collect();
+    shards[3].lock(&ikey(), &fingerprint("first")).await.unwrap();
+    shards[3].set(&ikey(), &cached_response()).await.unwrap();
+
+    // II. Act
+    let mut sizes = vec![];
+    for shard in shards.iter_mut() {
+        sizes.push(shard.size().await.unwrap());
+    }
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    let mut expired = vec![];
+    for shard in shards.iter_mut() {
+        expired.push(shard.expire().await.unwrap());
+    }
+
+    // III. Assert
+    assert_eq!(vec![1, 0, 0, 0], sizes);
+    assert_eq!(vec![1, 0, 0, 0], expired);
+}
+
+#[tokio::test]
+async fn duplicate_request_with_key_is_replayed_after_restart() {
+    // I. Arrange
+    let dir = TempDir::new().unwrap();
+    let client = hyper::Client::new();
+    let cache = open(&dir, &Default::default());
+    let before = TestApp::with_stores(UserRepository::new(), |i| cache.shard(i)).await;
+    let original = TestApp::with_idempotency(before.post_user(&before.test_user), 1);
+    spawn(async move { before.run().await.unwrap() });
+    let original = client.request(original).await.unwrap();
+
+    // II. Act
+    let cache = open(&dir, &Default::default());
+    let after = TestApp::with_stores(UserRepository::new(), |i| cache.shard(i)).await;
+    let duplicate = TestApp::with_idempotency(after.post_user(&after.test_user), 1);
+    spawn(async move { after.run().await.unwrap() });
+    let duplicate = client.request(duplicate).await.unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::OK, original.status());
+    assert_eq!(StatusCode::OK, duplicate.status());
+    assert_eq!("true", duplicate.headers().get("Idempotent-Replayed").unwrap());
+}
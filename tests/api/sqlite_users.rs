@@ -0,0 +1,331 @@
+use crate::cache::fingerprint;
+use crate::cache::ikey;
+use crate::test_app::TestApp;
+use lib::page::Cursor;
+use lib::page::Order;
+use lib::page::Page;
+use lib::page::UserQuery;
+use lib::page::UserSort;
+use lib::user::DeletedUser;
+use lib::user::NewUser;
+use lib::user::User;
+use lib::warehouse::CacheConfig;
+use lib::warehouse::CachedResponse;
+use lib::warehouse::Database;
+use lib::warehouse::EmailReuse;
+use lib::warehouse::IdempotencyStore;
+use lib::warehouse::Lock;
+use lib::warehouse::SqliteCache;
+use lib::warehouse::SqliteUserRepository;
+use lib::warehouse::UserRepoError;
+use lib::warehouse::UserStore;
+
+use hyper::body::to_bytes as BodyToBytes;
+use hyper::Body;
+use hyper::HeaderMap;
+use hyper::Request;
+use hyper::StatusCode;
+use rusqlite::Connection;
+use serde_json::Value;
+use tempfile::TempDir;
+use tokio::spawn;
+
+fn open(dir: &TempDir) -> SqliteUserRepository {
+    let db = Database::open(dir.path().join("icapi.db")).unwrap();
+    SqliteUserRepository::new(db)
+}
+
+#[test]
+fn users_survive_reopening() {
+    // I. Arrange
+    let dir = TempDir::new().unwrap();
+    let mut users = open(&dir);
+    let first = users.create(&NewUser::new("first@email".to_string())).unwrap();
+    let second = users.create(&NewUser::new("second@email".to_string())).unwrap();
+    drop(users);
+
+    // II. Act
+    let reopened = open(&dir);
+
+    // III. Assert
+    assert_eq!(first, reopened.get(first.id).unwrap());
+    assert_eq!(vec![first, second], reopened.list(&UserQuery::default()).unwrap().users);
+}
+
+#[test]
+fn taken_email_is_rejected() {
+    // I. Arrange
+    let dir = TempDir::new().unwrap();
+    let mut users = open(&dir);
+    users.create(&NewUser::new("first@email".to_string())).unwrap();
+
+    // II. Act
+    let duplicate = users.create(&NewUser::new("first@email".to_string()));
+
+    // III. Assert
+    assert!(matches!(duplicate, Err(UserRepoError::EmailTaken(_))));
+    assert!(matches!(users.get(2), Err(UserRepoError::UserNotFound(2))));
+}
+
+#[tokio::test]
+async fn created_user_is_found_after_restart() {
+    // I. Arrange
+    let dir = TempDir::new().unwrap();
+    let client = hyper::Client::new();
+    let before = TestApp::new(open(&dir)).await;
+    let create = before.post_user(&before.test_user);
+    spawn(async move { before.run().await.unwrap() });
+    let created = client.request(create).await.unwrap();
+    assert_eq!(StatusCode::OK, created.status());
+
+    // II. Act
+    let after = TestApp::new(open(&dir)).await;
+    let get = Request::builder().uri(format!("{}/users/1", after.address)).body(Body::empty());
+    spawn(async move { after.run().await.unwrap() });
+    let found = client.request(get.unwrap()).await.unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::OK, found.status());
+    let found = BodyToBytes(found.into_body()).await.unwrap();
+    let found: Value = serde_json::from_slice(&found).unwrap();
+    assert_eq!("first@email", found["email"]);
+}
+
+#[tokio::test]
+async fn created_user_is_recorded_in_the_same_transaction() {
+    // I. Arrange
+    let dir = TempDir::new().unwrap();
+    let db = Database::open(dir.path().join("icapi.db")).unwrap();
+    let mut cache = SqliteCache::new(db.clone(), &CacheConfig::default());
+    let mut users = SqliteUserRepository::new(db).recording_in(&cache);
+    cache.lock(&ikey(), &fingerprint("first")).await.unwrap();
+    let render = |user: &User| {
+        let body = serde_json::to_vec(user).unwrap().into();
+        let fingerprint = fingerprint("first");
+        CachedResponse { status: StatusCode::OK, headers: HeaderMap::new(), body, fingerprint }
+    };
+
+    // II. Act
+    let user = users.create_recorded(&NewUser::new("first@email".to_string()), &ikey(), &render);
+    // The cache is never `set`, as if the server crashed after the handler.
+    drop(cache);
+
+    // III. Assert
+    let user = user.unwrap();
+    let mut cache =
+        SqliteCache::new(Database::open(dir.path().join("icapi.db")).unwrap(), &Default::default());
+    let cached = cache.get(&ikey()).await.unwrap();
+    assert_eq!(StatusCode::OK, cached.status);
+    assert_eq!(serde_json::to_vec(&user).unwrap(), cached.body);
+}
+
+#[tokio::test]
+async fn user_is_not_recorded_for_a_cache_elsewhere() {
+    // I. Arrange
+    let dir = TempDir::new().unwrap();
+    let db = Database::open(dir.path().join("icapi.db")).unwrap();
+    let elsewhere = SqliteCache::new(Database::in_memory().unwrap(), &CacheConfig::default());
+    let mut users = SqliteUserRepository::new(db.clone()).recording_in(&elsewhere);
+    let render = |_: &User| panic!("Nothing to render for a cache elsewhere");
+
+    // II. Act
+    let user = users.create_recorded(&NewUser::new("first@email".to_string()), &ikey(), &render);
+
+    // III. Assert
+    assert_eq!(user.unwrap(), users.get(1).unwrap());
+    let mut cache = SqliteCache::new(db, &CacheConfig::default());
+    assert!(cache.get(&ikey()).await.is_err());
+}
+
+#[tokio::test]
+async fn failed_creation_is_not_recorded() {
+    // I. Arrange
+    let dir = TempDir::new().unwrap();
+    let db = Database::open(dir.path().join("icapi.db")).unwrap();
+    let mut cache = SqliteCache::new(db.clone(), &CacheConfig::default());
+    let mut users = SqliteUserRepository::new(db).recording_in(&cache);
+    users.create(&NewUser::new("first@email".to_string())).unwrap();
+    cache.lock(&ikey(), &fingerprint("first")).await.unwrap();
+    let render = |_: &User| panic!("Nothing to render for a taken email");
+
+    // II. Act
+    let user = users.create_recorded(&NewUser::new("first@email".to_string()), &ikey(), &render);
+
+    // III. Assert
+    assert!(matches!(user, Err(UserRepoError::EmailTaken(_))));
+    let lock = cache.lock(&ikey(), &fingerprint("first")).await.unwrap();
+    assert!(matches!(lock, Lock::InFlight));
+}
+
+#[test]
+fn update_keeps_emails_unique() {
+    // I. Arrange
+    let dir = TempDir::new().unwrap();
+    let mut users = open(&dir);
+    let first = users.create(&NewUser::new("first@email".to_string())).unwrap();
+    users.create(&NewUser::new("second@email".to_string())).unwrap();
+
+    // II. Act
+    let taken = users.update(&User::new(first.id, "second@email".to_string()));
+    let unchanged = users.update(&first);
+    let missing = users.update(&User::new(9, "ninth@email".to_string()));
+
+    // III. Assert
+    assert!(matches!(taken, Err(UserRepoError::EmailTaken(_))));
+    assert_eq!(first, unchanged.unwrap());
+    assert!(matches!(missing, Err(UserRepoError::UserNotFound(9))));
+}
+
+#[tokio::test]
+async fn updated_user_is_recorded_in_the_same_transaction() {
+    // I. Arrange
+    let dir = TempDir::new().unwrap();
+    let db = Database::open(dir.path().join("icapi.db")).unwrap();
+    let mut cache = SqliteCache::new(db.clone(), &CacheConfig::default());
+    let mut users = SqliteUserRepository::new(db).recording_in(&cache);
+    let first = users.create(&NewUser::new("first@email".to_string())).unwrap();
+    cache.lock(&ikey(), &fingerprint("first")).await.unwrap();
+    let render = |user: &User| {
+        let body = serde_json::to_vec(user).unwrap().into();
+        let fingerprint = fingerprint("first");
+        CachedResponse { status: StatusCode::OK, headers: HeaderMap::new(), body, fingerprint }
+    };
+
+    // II. Act
+    let user = User::new(first.id, "new@email".to_string());
+    let user = users.update_recorded(&user, &ikey(), &render);
+    // The cache is never `set`, as if the server crashed after the handler.
+    drop(cache);
+
+    // III. Assert
+    let user = user.unwrap();
+    let mut cache =
+        SqliteCache::new(Database::open(dir.path().join("icapi.db")).unwrap(), &Default::default());
+    let cached = cache.get(&ikey()).await.unwrap();
+    assert_eq!(serde_json::to_vec(&user).unwrap(), cached.body);
+    assert_eq!("new@email", users.get(first.id).unwrap().email);
+}
+
+#[test]
+fn tombstones_keep_emails_until_purged() {
+    // I. Arrange
+    let dir = TempDir::new().unwrap();
+    let mut users = open(&dir).with_email_reuse(EmailReuse::OnPurge);
+    let first = users.create(&NewUser::new("first@email".to_string())).unwrap();
+
+    // II. Act
+    let deleted = users.delete(first.id).unwrap();
+    let taken = users.create(&NewUser::new("first@email".to_string()));
+    let hidden = users.get(first.id);
+    users.purge(first.id).unwrap();
+    let created = users.create(&NewUser::new("first@email".to_string()));
+
+    // III. Assert
+    assert_eq!(first, deleted.user);
+    assert!(matches!(taken, Err(UserRepoError::EmailTaken(_))));
+    assert!(matches!(hidden, Err(UserRepoError::UserNotFound(_))));
+    assert_eq!(vec![created.unwrap()], users.list(&UserQuery::default()).unwrap().users);
+}
+
+#[test]
+fn purged_id_is_not_reused() {
+    // I. Arrange
+    let dir = TempDir::new().unwrap();
+    let mut users = open(&dir);
+    users.create(&NewUser::new("first@email".to_string())).unwrap();
+    let last = users.create(&NewUser::new("second@email".to_string())).unwrap();
+    users.purge(last.id).unwrap();
+    drop(users);
+
+    // II. Act
+    let mut reopened = open(&dir);
+    let created = reopened.create(&NewUser::new("third@email".to_string())).unwrap();
+
+    // III. Assert
+    assert_ne!(last.id, created.id);
+}
+
+#[tokio::test]
+async fn deleted_user_is_recorded_in_the_same_transaction() {
+    // I. Arrange
+    let dir = TempDir::new().unwrap();
+    let db = Database::open(dir.path().join("icapi.db")).unwrap();
+    let mut cache = SqliteCache::new(db.clone(), &CacheConfig::default());
+    let mut users = SqliteUserRepository::new(db).recording_in(&cache);
+    let first = users.create(&NewUser::new("first@email".to_string())).unwrap();
+    cache.lock(&ikey(), &fingerprint("first")).await.unwrap();
+    let render = |deleted: &DeletedUser| {
+        let body = serde_json::to_vec(deleted).unwrap().into();
+        let fingerprint = fingerprint("first");
+        CachedResponse { status: StatusCode::OK, headers: HeaderMap::new(), body, fingerprint }
+    };
+
+    // II. Act
+    let deleted = users.delete_recorded(first.id, &ikey(), &render);
+    // The cache is never `set`, as if the server crashed after the handler.
+    drop(cache);
+
+    // III. Assert
+    let deleted = deleted.unwrap();
+    let mut cache =
+        SqliteCache::new(Database::open(dir.path().join("icapi.db")).unwrap(), &Default::default());
+    let cached = cache.get(&ikey()).await.unwrap();
+    assert_eq!(serde_json::to_vec(&deleted).unwrap(), cached.body);
+    assert!(users.list(&UserQuery::default()).unwrap().users.is_empty());
+}
+
+#[test]
+fn users_survive_the_tombstones_migration() {
+    // I. Arrange
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("icapi.db");
+    let conn = Connection::open(&path).unwrap();
+    // The schema as of the second migration.
+    let schema =
+        "CREATE TABLE idempotency (key TEXT PRIMARY KEY NOT NULL, fingerprint TEXT NOT NULL,
+            status INTEGER, headers TEXT, body BLOB, created_at INTEGER NOT NULL);
+        CREATE TABLE users (id INTEGER PRIMARY KEY NOT NULL, email TEXT NOT NULL UNIQUE);
+        INSERT INTO users (id, email) VALUES (7, 'first@email');
+        PRAGMA user_version = 2;";
+    conn.execute_batch(schema).unwrap();
+    drop(conn);
+
+    // II. Act
+    let mut users = SqliteUserRepository::new(Database::open(&path).unwrap());
+
+    // III. Assert
+    assert_eq!(User::new(7, "first@email".to_string()), users.get(7).unwrap());
+    let taken = users.create(&NewUser::new("first@email".to_string()));
+    assert!(matches!(taken, Err(UserRepoError::EmailTaken(_))));
+    users.delete(7).unwrap();
+    assert!(users.create(&NewUser::new("first@email".to_string())).is_ok());
+}
+
+#[test]
+fn users_are_paged_in_the_database() {
+    // I. Arrange
+    let dir = TempDir::new().unwrap();
+    let mut users = open(&dir);
+    for email in ["b@email", "d@email", "a@email", "c@email", "e@other"] {
+        users.create(&NewUser::new(email.to_string())).unwrap();
+    }
+    users.delete(4).unwrap();
+    let query = UserQuery {
+        limit: 2,
+        sort: UserSort::Email,
+        order: Order::Desc,
+        email_contains: Some("@email".to_string()),
+        ..Default::default()
+    };
+
+    // II. Act
+    let first = users.list(&query).unwrap();
+    let second = users.list(&UserQuery { after: first.next.clone(), ..query.clone() }).unwrap();
+
+    // III. Assert
+    let emails = |page: &Page| page.users.iter().map(|user| user.email.clone()).collect::<Vec<_>>();
+    assert_eq!(vec!["d@email", "b@email"], emails(&first));
+    assert_eq!(Some(Cursor::Email("b@email".to_string(), 1)), first.next);
+    assert_eq!(vec!["a@email"], emails(&second));
+    assert_eq!(None, second.next);
+}
@@ -1,8 +1,12 @@
+use lib::middleware::cache::IdempotencyConfig;
 use lib::obs;
 use lib::obs::get_sub;
 use lib::server::UserApi;
 use lib::user::NewUser;
+use lib::warehouse::Cache;
+use lib::warehouse::IdempotencyStore;
 use lib::warehouse::UserRepository;
+use lib::warehouse::UserStore;
 
 use axum::Router;
 use hyper::header;
@@ -11,6 +15,8 @@ use hyper::Body;
 use hyper::Method;
 use hyper::Request;
 use serde_json::json;
+use serde_json::Value;
+use std::future::Future;
 use std::net::Ipv4Addr;
 use std::sync::LazyLock;
 use tokio::net::TcpListener;
@@ -26,8 +32,9 @@ static TRACING: LazyLock<()> = LazyLock::new(|| {
     }
 });
 
+#[allow(dead_code)]
 pub struct TestApp {
-    pub pool: UserRepository,
+    pub pool: Box<dyn UserStore>,
     pub app: UserApi,
     pub address: String,
     pub port: u16,
@@ -35,14 +42,27 @@ pub struct TestApp {
 }
 
 impl TestApp {
-    pub async fn new(pool: UserRepository) -> Self {
+    pub async fn new(pool: impl UserStore + Clone) -> Self {
+        let config = IdempotencyConfig::default();
+        let shard = config.cache.shard(config.shards);
+        Self::with_stores(pool, |_| Cache::with_config(&shard)).await
+    }
+
+    /// Initialize with the responses of shard `i` cached in the store
+    /// `make(i)`.
+    pub async fn with_stores<S: IdempotencyStore>(
+        pool: impl UserStore + Clone,
+        make: impl FnMut(usize) -> S,
+    ) -> Self {
         LazyLock::force(&TRACING);
         let socket = (Ipv4Addr::new(127, 0, 0, 1), 0);
         let listener = TcpListener::bind(socket).await.unwrap();
         let port = listener.local_addr().unwrap().port();
         let address = format!("http://localhost:{}", port);
-        let app = UserApi::new(listener, pool.clone());
+        let config = IdempotencyConfig::default();
+        let app = UserApi::with_stores(listener, pool.clone(), config, make);
         let test_user = NewUser::new("first@email".to_string());
+        let pool = Box::new(pool);
         Self { pool, app, address, port, test_user }
     }
 
@@ -67,6 +87,14 @@ impl TestApp {
         self.app.run().await
     }
 
+    /// Runs the actual server until `shutdown` completes.
+    pub async fn run_until(
+        self,
+        shutdown: impl Future<Output = ()> + Send + 'static,
+    ) -> Result<(), color_eyre::Report> {
+        self.app.run_until(shutdown).await
+    }
+
     pub fn router(&self) -> Router {
         self.app.api.clone()
     }
@@ -80,10 +108,47 @@ impl TestApp {
             .unwrap()
     }
 
+    pub fn put_user(&self, id: u64, user: &NewUser) -> Request<Body> {
+        self.update_user(Method::PUT, id, &json!(user))
+    }
+
+    pub fn patch_user(&self, id: u64, patch: &Value) -> Request<Body> {
+        self.update_user(Method::PATCH, id, patch)
+    }
+
+    fn update_user(&self, method: Method, id: u64, body: &Value) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .uri(format!("{}/users/{id}", self.address))
+            .body(Body::from(serde_json::to_vec(body).unwrap()))
+            .unwrap()
+    }
+
+    pub fn delete_user(&self, id: u64) -> Request<Body> {
+        let uri = format!("{}/users/{id}", self.address);
+        Request::builder().method(Method::DELETE).uri(uri).body(Body::empty()).unwrap()
+    }
+
+    pub fn purge_user(&self, id: u64) -> Request<Body> {
+        let uri = format!("{}/users/{id}?purge=true", self.address);
+        Request::builder().method(Method::DELETE).uri(uri).body(Body::empty()).unwrap()
+    }
+
+    pub fn get_user(&self, id: u64) -> Request<Body> {
+        Request::builder().uri(format!("{}/users/{id}", self.address)).body(Body::empty()).unwrap()
+    }
+
     pub fn get_users(&self) -> Request<Body> {
         Request::builder().uri(format!("{}/users", self.address)).body(Body::empty()).unwrap()
     }
 
+    /// `GET /users` with the given query string.
+    pub fn list_users(&self, query: &str) -> Request<Body> {
+        let uri = format!("{}/users?{query}", self.address);
+        Request::builder().uri(uri).body(Body::empty()).unwrap()
+    }
+
     pub fn with_idempotency(req: Request<Body>, key: u64) -> Request<Body> {
         let mut req = req;
         req.headers_mut().insert("Idempotency-Key", HeaderValue::from(key));
@@ -0,0 +1,162 @@
+use crate::test_app::TestApp;
+use lib::server::UserApi;
+use lib::user::NewUser;
+
+use axum::response::Response;
+use axum::Router;
+use hyper::body::to_bytes as BodyToBytes;
+use hyper::StatusCode;
+use serde_json::json;
+use serde_json::Value;
+use tokio::spawn;
+use tower::ServiceExt;
+
+async fn body(response: Response) -> Value {
+    let body = BodyToBytes(response.into_body()).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+/// The router of `app`, with its cache manager running.
+fn running(app: TestApp) -> Router {
+    let router = app.router();
+    let UserApi { mut cache_manager, .. } = app.app;
+    spawn(async move { cache_manager.run().await });
+    router
+}
+
+#[tokio::test]
+async fn patch_changes_email() {
+    // I. Arrange
+    let app = TestApp::new(TestApp::init_repo_data()).await;
+    let req = TestApp::with_idempotency(app.patch_user(1, &json!({ "email": "new@email" })), 1);
+    let get = app.get_user(1);
+    let router = running(app);
+
+    // II. Act
+    let response = router.clone().oneshot(req).await.unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::OK, response.status());
+    insta::assert_json_snapshot!(body(response).await);
+    let found = router.oneshot(get).await.unwrap();
+    assert_eq!("new@email", body(found).await["email"]);
+}
+
+#[tokio::test]
+async fn empty_patch_changes_nothing() {
+    // I. Arrange
+    let app = TestApp::new(TestApp::init_repo_data()).await;
+    let req = TestApp::with_idempotency(app.patch_user(1, &json!({})), 1);
+
+    // II. Act
+    let response = running(app).oneshot(req).await.unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(json!({ "id": 1, "email": "first@email" }), body(response).await);
+}
+
+#[tokio::test]
+async fn put_replaces_user() {
+    // I. Arrange
+    let app = TestApp::new(TestApp::init_repo_data()).await;
+    let req = TestApp::with_idempotency(app.put_user(2, &NewUser::new("new@email".to_string())), 1);
+
+    // II. Act
+    let response = running(app).oneshot(req).await.unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(json!({ "id": 2, "email": "new@email" }), body(response).await);
+}
+
+#[tokio::test]
+async fn invalid_updates_are_rejected() {
+    // I. Arrange
+    let app = TestApp::new(TestApp::init_repo_data()).await;
+    let requests = [
+        app.patch_user(1, &json!({ "email": "second@email" })),
+        app.put_user(1, &NewUser::new("third@email".to_string())),
+        app.patch_user(1, &json!({ "email": "" })),
+        app.patch_user(9, &json!({ "email": "new@email" })),
+        app.patch_user(1, &json!({ "name": "first" })),
+    ];
+    let router = running(app);
+
+    // II. Act
+    let mut responses = Vec::new();
+    for (key, req) in (1..).zip(requests) {
+        let req = TestApp::with_idempotency(req, key);
+        let response = router.clone().oneshot(req).await.unwrap();
+        responses.push(response.status().as_u16());
+    }
+
+    // III. Assert
+    assert_eq!(vec![409, 409, 400, 404, 422], responses);
+}
+
+#[tokio::test]
+async fn retried_update_does_not_clobber_later_change() {
+    // I. Arrange
+    let app = TestApp::new(TestApp::init_repo_data()).await;
+    let first = TestApp::with_idempotency(app.patch_user(1, &json!({ "email": "a@email" })), 1);
+    let later = TestApp::with_idempotency(app.patch_user(1, &json!({ "email": "b@email" })), 2);
+    let retry = TestApp::with_idempotency(app.patch_user(1, &json!({ "email": "a@email" })), 1);
+    let get = app.get_user(1);
+    let router = app.router();
+    let UserApi { mut cache_manager, .. } = app.app;
+    spawn(async move { cache_manager.run().await });
+
+    // II. Act
+    let first = router.clone().oneshot(first).await.unwrap();
+    let later = router.clone().oneshot(later).await.unwrap();
+    let retry = router.clone().oneshot(retry).await.unwrap();
+    let found = router.oneshot(get).await.unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::OK, first.status());
+    assert_eq!(StatusCode::OK, later.status());
+    assert_eq!("true", retry.headers()["Idempotent-Replayed"]);
+    assert_eq!(body(first).await, body(retry).await);
+    assert_eq!("b@email", body(found).await["email"]);
+}
+
+#[tokio::test]
+async fn retry_without_key_is_rejected_after_later_change() {
+    // I. Arrange
+    let app = TestApp::new(TestApp::init_repo_data()).await;
+    let first = TestApp::with_idempotency(app.patch_user(1, &json!({ "email": "a@email" })), 1);
+    let later = TestApp::with_idempotency(app.patch_user(1, &json!({ "email": "b@email" })), 2);
+    let retry = app.patch_user(1, &json!({ "email": "a@email" }));
+    let get = app.get_user(1);
+    let router = running(app);
+
+    // II. Act
+    router.clone().oneshot(first).await.unwrap();
+    router.clone().oneshot(later).await.unwrap();
+    let retry = router.clone().oneshot(retry).await.unwrap();
+    let found = router.oneshot(get).await.unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::BAD_REQUEST, retry.status());
+    assert_eq!("b@email", body(found).await["email"]);
+}
+
+#[tokio::test]
+async fn key_reused_for_another_user_is_422() {
+    // I. Arrange
+    let app = TestApp::new(TestApp::init_repo_data()).await;
+    let first = TestApp::with_idempotency(app.patch_user(1, &json!({ "email": "a@email" })), 1);
+    let other = TestApp::with_idempotency(app.patch_user(2, &json!({ "email": "a@email" })), 1);
+    let router = app.router();
+    let UserApi { mut cache_manager, .. } = app.app;
+    spawn(async move { cache_manager.run().await });
+
+    // II. Act
+    let first = router.clone().oneshot(first).await.unwrap();
+    let other = router.oneshot(other).await.unwrap();
+
+    // III. Assert
+    assert_eq!(StatusCode::OK, first.status());
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, other.status());
+}